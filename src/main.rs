@@ -1,10 +1,46 @@
-use clap::Parser;
-use std::collections::HashMap;
+mod manifest;
+mod provider;
+mod providers;
+mod reference;
+mod retry;
+mod secret;
+mod supervise;
+mod template;
+
+use clap::{CommandFactory, Parser};
+use manifest::{Manifest, ManifestError, Transform};
+use provider::{Chain, ProviderError, Registry, Resolution};
+use providers::VaultAuth;
+use reference::{Case, EXPAND_SUFFIX, Reference};
+use retry::RetryPolicy;
+use secret::Secret;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
+use template::TEMPLATE_SCHEME;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None, name = "Environment Loader")]
+#[command(args_override_self = true)]
 struct Application {
+    ///
+    /// Manifest declaring the variables to load and defaults for these options.
+    ///
+    /// Options given on the command line override the manifest's settings,
+    /// and references in the environment override its variables.
+    ///
+    #[arg(long, env = "ENV_LOADER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    ///
+    /// Profile of the manifest to apply, such as a deployment stage.
+    ///
+    #[arg(long, env = "ENV_LOADER_PROFILE", requires = "config")]
+    pub profile: Option<String>,
+
     ///
     /// Specify a list of variables that should be passed through to the environment.
     ///
@@ -19,6 +55,9 @@ struct Application {
     ///
     /// Dont exit when a loadable variable is not found.
     ///
+    /// Only missing values are tolerated; access, decryption and network
+    /// errors still stop the loader.
+    ///
     #[arg(short, long, default_value_t = false)]
     pub ignore_missing: bool,
 
@@ -32,6 +71,219 @@ struct Application {
     #[arg(short, long)]
     pub env_prefix: Option<String>,
 
+    ///
+    /// Separator placed between a variable's name and each JSON key when a
+    /// `<scheme>_expand::` reference is expanded into several variables.
+    ///
+    #[arg(long, default_value = "_")]
+    pub expand_separator: String,
+
+    ///
+    /// Case conversion applied to JSON keys when expanding a reference.
+    ///
+    #[arg(long, value_enum, default_value_t = Case::Upper)]
+    pub expand_case: Case,
+
+    ///
+    /// Directory that binary values loaded with `binary=file` are written to.
+    ///
+    /// Must be owned by, and only writable by, the current user. Defaults to
+    /// a new, randomly named directory in the system temporary directory.
+    ///
+    #[arg(long)]
+    pub binary_dir: Option<PathBuf>,
+
+    ///
+    /// Allow `exec::` references, which run commands taken from the environment.
+    ///
+    /// Only enable this when the environment is trusted: anyone who can set a
+    /// variable can run any command as the loader's user.
+    ///
+    #[arg(long, default_value_t = false)]
+    pub allow_exec: bool,
+
+    ///
+    /// Maximum number of references resolved at the same time.
+    ///
+    #[arg(long, default_value = "10")]
+    pub concurrency: NonZeroUsize,
+
+    ///
+    /// Maximum number of attempts for each provider call.
+    ///
+    /// Only throttling and transport errors (timeouts, connection failures,
+    /// internal server errors) are retried.
+    ///
+    #[arg(long, default_value = "3")]
+    pub max_attempts: NonZeroU32,
+
+    ///
+    /// Delay before the first retry, doubled for each further retry.
+    ///
+    #[arg(long, default_value = "200ms", value_parser = humantime::parse_duration)]
+    pub retry_backoff: Duration,
+
+    ///
+    /// Upper bound on the delay between attempts.
+    ///
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    pub retry_max_backoff: Duration,
+
+    ///
+    /// Fraction of each retry delay that is randomised, from 0 (fixed
+    /// delays) to 1 (anywhere between zero and the full delay).
+    ///
+    #[arg(long, default_value = "0.5", value_parser = parse_fraction)]
+    pub retry_jitter: f64,
+
+    ///
+    /// Time limit for a single provider call.
+    ///
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    pub call_timeout: Duration,
+
+    ///
+    /// Time limit for resolving every reference, including retries.
+    ///
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub deadline: Option<Duration>,
+
+    ///
+    /// ARN of an IAM role to assume before loading any AWS reference.
+    ///
+    /// References can assume a different role with the `role` option.
+    ///
+    #[arg(long)]
+    pub assume_role: Option<String>,
+
+    ///
+    /// External ID passed to STS when assuming a role.
+    ///
+    #[arg(long)]
+    pub external_id: Option<String>,
+
+    ///
+    /// Session name used when assuming a role.
+    ///
+    #[arg(long, default_value = "environment-loader")]
+    pub role_session_name: String,
+
+    ///
+    /// Azure tenant of the application used for client secret auth.
+    ///
+    #[arg(long, env = "AZURE_TENANT_ID")]
+    pub azure_tenant_id: Option<String>,
+
+    ///
+    /// Azure application (client) ID for client secret auth, or the client
+    /// ID of a user-assigned managed identity.
+    ///
+    #[arg(long, env = "AZURE_CLIENT_ID")]
+    pub azure_client_id: Option<String>,
+
+    ///
+    /// Azure client secret. When not given, the managed identity is used.
+    ///
+    /// Prefer the environment variable, which is not visible in the process list.
+    ///
+    #[arg(long, env = "AZURE_CLIENT_SECRET", hide_env_values = true)]
+    pub azure_client_secret: Option<String>,
+
+    ///
+    /// Microsoft Entra ID endpoint that client secret tokens are requested from.
+    ///
+    #[arg(
+        long,
+        env = "AZURE_AUTHORITY_HOST",
+        default_value = "https://login.microsoftonline.com"
+    )]
+    pub azure_authority_host: String,
+
+    ///
+    /// DNS suffix of Key Vault hosts, such as `vault.azure.cn` in Azure China.
+    ///
+    #[arg(long, default_value = "vault.azure.net")]
+    pub azure_vault_suffix: String,
+
+    ///
+    /// Address of the Vault server, such as `https://vault.example.com:8200`.
+    ///
+    #[arg(long, env = "VAULT_ADDR")]
+    pub vault_addr: Option<String>,
+
+    ///
+    /// Vault Enterprise namespace that requests are made in.
+    ///
+    #[arg(long, env = "VAULT_NAMESPACE")]
+    pub vault_namespace: Option<String>,
+
+    ///
+    /// How to authenticate to Vault.
+    ///
+    #[arg(long, value_enum, default_value_t = VaultAuth::Token)]
+    pub vault_auth: VaultAuth,
+
+    ///
+    /// Path the Vault auth method is mounted at.
+    ///
+    /// Defaults to the name of the method (`approle` or `kubernetes`).
+    ///
+    #[arg(long)]
+    pub vault_auth_mount: Option<String>,
+
+    ///
+    /// Vault token used with `--vault-auth token`.
+    ///
+    /// Defaults to the token saved in `~/.vault-token` by `vault login`.
+    ///
+    #[arg(long, env = "VAULT_TOKEN", hide_env_values = true)]
+    pub vault_token: Option<String>,
+
+    ///
+    /// AppRole role ID used with `--vault-auth approle`.
+    ///
+    #[arg(long, env = "VAULT_ROLE_ID")]
+    pub vault_role_id: Option<String>,
+
+    ///
+    /// AppRole secret ID used with `--vault-auth approle`.
+    ///
+    /// Prefer the environment variable, which is not visible in the process list.
+    ///
+    #[arg(long, env = "VAULT_SECRET_ID", hide_env_values = true)]
+    pub vault_secret_id: Option<String>,
+
+    ///
+    /// Vault role to log in as with `--vault-auth kubernetes`.
+    ///
+    #[arg(long)]
+    pub vault_role: Option<String>,
+
+    ///
+    /// Service account token presented with `--vault-auth kubernetes`.
+    ///
+    #[arg(
+        long,
+        default_value = "/var/run/secrets/kubernetes.io/serviceaccount/token"
+    )]
+    pub vault_kubernetes_token_path: PathBuf,
+
+    ///
+    /// Google Cloud Secret Manager API endpoint, for example a Private
+    /// Service Connect endpoint.
+    ///
+    #[arg(long, default_value = "https://secretmanager.googleapis.com")]
+    pub gcp_endpoint: String,
+
+    ///
+    /// Run the command as a child process instead of replacing the loader.
+    ///
+    /// The loader stays running to renew leases on dynamic secrets until the
+    /// command exits, then revokes them and exits with the command's status.
+    ///
+    #[arg(long, default_value_t = false)]
+    pub supervise: bool,
+
     ///
     /// The command to run with the environment variables loaded.
     ///
@@ -39,56 +291,243 @@ struct Application {
     pub cmd: Vec<String>,
 }
 
-#[derive(Default)]
-pub struct Amazon {
-    config: Option<aws_config::SdkConfig>,
-    secrets_client: Option<aws_sdk_secretsmanager::Client>,
+impl Application {
+    ///
+    /// Parse the command line, applying the settings of the `--config`
+    /// manifest (if any) underneath the options given on it.
+    ///
+    fn load() -> (Self, Option<Manifest>) {
+        let application = Application::parse();
+
+        let Some(path) = &application.config else {
+            return (application, None);
+        };
+
+        let mut manifest = Manifest::load(path).unwrap_or_else(|error| {
+            tracing::error!("{}", error);
+            std::process::exit(1);
+        });
+
+        let profile = match &application.profile {
+            Some(profile) => manifest.select_profile(profile),
+            None => Ok(()),
+        };
+        if let Err(error) = profile.and_then(|_| manifest.substitute_placeholders()) {
+            tracing::error!("{}", error);
+            std::process::exit(1);
+        }
+
+        let application = Application::parse_with_settings(&manifest, std::env::args_os())
+            .unwrap_or_else(|error| {
+                tracing::error!("{}", error);
+                std::process::exit(1);
+            });
+
+        (application, Some(manifest))
+    }
+
+    ///
+    /// Parse `args` with the manifest's settings applied underneath them.
+    ///
+    fn parse_with_settings(
+        manifest: &Manifest,
+        args: impl IntoIterator<Item = OsString>,
+    ) -> Result<Self, ManifestError> {
+        let settings = manifest.settings_args(&Application::command())?;
+
+        // Later occurrences of an option override earlier ones, so the
+        // manifest's settings go first
+        let mut args = args.into_iter().collect::<Vec<_>>();
+        args.splice(1..1, settings);
+
+        Ok(Application::parse_from(args))
+    }
+}
+
+///
+/// A reference to resolve, and how its value is exported.
+///
+struct Request {
+    ///
+    /// The environment variable or manifest entry the reference came from.
+    ///
+    key: String,
+
+    ///
+    /// The variable the value is exported as.
+    ///
+    name: String,
+    source: Source,
+
+    ///
+    /// Whether a missing value fails the loader (unless `--ignore-missing`).
+    ///
+    required: bool,
+    default: Option<String>,
+    transforms: Vec<Transform>,
+}
+
+///
+/// Where a variable's value comes from.
+///
+enum Source {
+    ///
+    /// References tried in order until one loads (`a::x || b::y`).
+    ///
+    References(Vec<Reference>),
+
+    ///
+    /// A `template::` value, rendered once every reference has been loaded.
+    ///
+    Template(String),
 }
 
-impl Amazon {
-    pub fn new() -> Self {
-        Self::default()
+impl Request {
+    fn transform(&self, value: String) -> Result<String, ProviderError> {
+        self.transforms
+            .iter()
+            .try_fold(value, |value, transform| transform.apply(value))
     }
+}
 
-    pub async fn get_config(&mut self) -> &aws_config::SdkConfig {
-        let config = &mut self.config;
-        if config.is_some() {
-            config.as_ref().unwrap()
-        } else {
-            let amazon = aws_config::defaults(aws_config::BehaviorVersion::v2025_01_17())
-                .load()
-                .await;
+///
+/// Parse the references or template in `value`, registering plugins for
+/// schemes that are not built in.
+///
+/// Returns `None` for values that are not references, and otherwise the
+/// source and whether it is marked optional with `scheme?::`. A scheme that
+/// is neither built in nor provided by a plugin is `NotFound`.
+///
+fn parse_source(
+    registry: &mut Registry,
+    value: &str,
+) -> Option<(Result<Source, ProviderError>, bool)> {
+    // `||` only separates fallbacks when every side of it is a reference
+    let segments = value.split("||").map(str::trim).collect::<Vec<_>>();
+    let segments = if segments.len() > 1 && segments.iter().all(|segment| segment.contains("::")) {
+        segments
+    } else {
+        vec![value]
+    };
+
+    let mut parts = Vec::new();
+    let mut optional = false;
 
-            *config = Some(amazon);
+    for segment in &segments {
+        let (load_method, remainder) = segment.split_once("::")?;
+
+        // Marking any reference of a fallback chain optional marks the variable
+        let load_method = match load_method.strip_suffix('?') {
+            Some(load_method) => {
+                optional = true;
+                load_method
+            }
+            None => load_method,
+        };
 
-            config.as_ref().unwrap()
+        parts.push((load_method, remainder));
+    }
+
+    let source = match parts.as_slice() {
+        [(TEMPLATE_SCHEME, template)] => Ok(Source::Template(template.to_string())),
+        parts
+            if parts
+                .iter()
+                .any(|(load_method, _)| *load_method == TEMPLATE_SCHEME) =>
+        {
+            Err(ProviderError::Invalid(
+                "templates cannot be fallbacks".to_string(),
+            ))
         }
+        parts => parts
+            .iter()
+            .map(|(load_method, remainder)| parse_reference(registry, load_method, remainder))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Source::References),
+    };
+
+    Some((source, optional))
+}
+
+///
+/// Parse a `load_method::remainder` reference, registering a plugin for its
+/// scheme if it is not built in.
+///
+fn parse_reference(
+    registry: &mut Registry,
+    load_method: &str,
+    remainder: &str,
+) -> Result<Reference, ProviderError> {
+    let (scheme, expand) = match load_method.strip_suffix(EXPAND_SUFFIX) {
+        Some(scheme) => (scheme, true),
+        None => (load_method, false),
+    };
+
+    if registry.get(scheme).is_none()
+        && let Some(plugin) = providers::Plugin::discover(scheme)
+    {
+        tracing::debug!("Using plugin {} for {}", plugin.program.display(), scheme);
+        registry.register(plugin);
     }
 
-    pub async fn get_secret(&mut self, secret_name: &str) -> Option<String> {
-        if let Some(client) = self.secrets_client.as_ref() {
-            let response = client
-                .get_secret_value()
-                .secret_id(secret_name)
-                .send()
-                .await;
+    let Some(provider) = registry.get(scheme) else {
+        return Err(ProviderError::NotFound(format!(
+            "unknown load method {load_method} (no {}{scheme} plugin on PATH)",
+            providers::PLUGIN_PREFIX
+        )));
+    };
+    if expand && !provider.capabilities().json_fields {
+        return Err(ProviderError::Invalid(format!(
+            "load method {scheme} does not return JSON and cannot be expanded"
+        )));
+    }
 
-            response.ok()?.secret_string().map(String::from)
-        } else {
-            let config = self.get_config().await;
+    Reference::parse(scheme, remainder, provider, expand)
+}
 
-            let new_secrets_client = aws_sdk_secretsmanager::Client::new(config);
+///
+/// The name a loaded variable is exported under: the key with the
+/// `--env-prefix` removed, if it has one.
+///
+fn variable_name(key: &str, prefix: Option<&str>) -> String {
+    prefix
+        .and_then(|prefix| key.strip_prefix(prefix))
+        .unwrap_or(key)
+        .to_string()
+}
 
-            let response = new_secrets_client
-                .get_secret_value()
-                .secret_id(secret_name)
-                .send()
-                .await;
+///
+/// Whether a variable may be left unset after failing with `error`. Only
+/// values that are not found are tolerated, and only for optional variables
+/// or with `--ignore-missing`.
+///
+fn tolerated(error: &ProviderError, required: bool, ignore_missing: bool) -> bool {
+    error.is_not_found() && (ignore_missing || !required)
+}
 
-            self.secrets_client = Some(new_secrets_client);
+///
+/// The message listing the required variables that could not be loaded, in
+/// name order.
+///
+fn failure_summary(mut failed: Vec<String>) -> String {
+    failed.sort();
+    let variables = if failed.len() == 1 {
+        "variable"
+    } else {
+        "variables"
+    };
 
-            response.ok()?.secret_string().map(String::from)
-        }
+    format!(
+        "{} required {variables} could not be loaded: {}",
+        failed.len(),
+        failed.join(", ")
+    )
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("{value} is not a number between 0 and 1")),
     }
 }
 
@@ -98,9 +537,10 @@ async fn main() {
         .with_max_level(tracing::Level::INFO)
         .init();
 
-    let application = Application::parse();
+    let (application, manifest) = Application::load();
 
-    let mut variables = std::env::vars().collect::<HashMap<String, String>>();
+    // Sorted, so that failures are always reported in the same order
+    let mut variables = std::env::vars().collect::<BTreeMap<String, String>>();
 
     let mut passed_variables = HashMap::<String, String>::new();
 
@@ -124,68 +564,235 @@ async fn main() {
         }
     }
 
-    let mut amazon = Amazon::new();
+    let mut registry = providers::registry(&application);
+
+    let mut requests = Vec::new();
+
+    // Required variables that could not be loaded, reported together at the end
+    let mut failed = Vec::new();
+
+    // Only a missing provider is tolerated, like a missing value
+    let mut invalid = |key: &str, error: ProviderError, required: bool| {
+        if tolerated(&error, required, application.ignore_missing) {
+            tracing::warn!("Invalid reference in variable {}: {}", key, error);
+        } else {
+            tracing::error!("Invalid reference in variable {}: {}", key, error);
+            failed.push(key.to_string());
+        }
+    };
 
     for (key, value) in variables {
-        if value.contains("::") {
-            let (load_method, remainder) = value.split_once("::").unwrap();
-
-            match load_method {
-                "value" => {
-                    // Pass the remainder as the value directly
-                    if let Some(prefix) = &application.env_prefix {
-                        if key.starts_with(prefix) {
-                            passed_variables.insert(
-                                key.strip_prefix(prefix).unwrap().to_string(),
-                                remainder.to_string(),
-                            );
-                        } else {
-                            passed_variables.insert(key, remainder.to_string());
-                        }
+        match parse_source(&mut registry, &value) {
+            Some((Ok(source), optional)) => requests.push(Request {
+                name: variable_name(&key, application.env_prefix.as_deref()),
+                key,
+                source,
+                required: !optional,
+                default: None,
+                transforms: Vec::new(),
+            }),
+            Some((Err(error), optional)) => invalid(&key, error, !optional),
+            None => {}
+        }
+    }
+
+    for (name, variable) in manifest
+        .map(|manifest| manifest.variables)
+        .unwrap_or_default()
+    {
+        // A reference in the environment overrides the manifest's entry
+        if requests.iter().any(|request| request.name == name) {
+            tracing::debug!(
+                "Variable {} is set in the environment, ignoring the manifest entry",
+                name
+            );
+            continue;
+        }
+
+        match parse_source(&mut registry, &variable.source) {
+            Some((Ok(source), optional)) => requests.push(Request {
+                key: name.clone(),
+                name,
+                source,
+                required: variable.required && !optional,
+                default: variable.default,
+                transforms: variable.transforms,
+            }),
+            Some((Err(error), optional)) => invalid(&name, error, variable.required && !optional),
+            None => invalid(
+                &name,
+                ProviderError::Invalid(format!("{} is not a reference", variable.source)),
+                true,
+            ),
+        }
+    }
+
+    let policy = RetryPolicy {
+        max_attempts: application.max_attempts,
+        initial_backoff: application.retry_backoff,
+        max_backoff: application.retry_max_backoff,
+        jitter: application.retry_jitter,
+        call_timeout: application.call_timeout,
+        deadline: application
+            .deadline
+            .map(|deadline| tokio::time::Instant::now() + deadline),
+    };
+
+    let binary_dir = secret::BinaryDirectory::new(application.binary_dir.clone());
+
+    let (chained, chains): (Vec<_>, Vec<_>) = requests
+        .iter()
+        .filter_map(|request| match &request.source {
+            Source::References(references) => Some((
+                request,
+                Chain {
+                    key: &request.key,
+                    references,
+                    default: request.default.as_deref(),
+                },
+            )),
+            Source::Template(_) => None,
+        })
+        .unzip();
+
+    let load = |index: usize, reference: &Reference, secret: Secret| {
+        let request: &Request = chained[index];
+        let name = request.name.clone();
+
+        let loaded = if reference.expand {
+            reference
+                .expand(secret.into_text()?)?
+                .into_iter()
+                .map(|(json_key, value)| {
+                    let name = reference::expanded_name(
+                        &name,
+                        &application.expand_separator,
+                        &json_key,
+                        application.expand_case,
+                    );
+                    (name, value)
+                })
+                .collect()
+        } else if reference.field.is_some() {
+            vec![(name, reference.select(secret.into_text()?)?)]
+        } else {
+            let value = secret.into_value(reference.binary, &name, &binary_dir)?;
+            vec![(name, value)]
+        };
+
+        loaded
+            .into_iter()
+            .map(|(name, value)| Ok((name, request.transform(value)?)))
+            .collect::<Result<Vec<_>, ProviderError>>()
+    };
+
+    let resolutions = registry
+        .resolve_chains(&chains, application.concurrency, &policy, load)
+        .await;
+
+    for (request, resolution) in chained.iter().zip(resolutions) {
+        match resolution {
+            Resolution::Loaded(reference, loaded) => {
+                let sensitive = registry
+                    .get(&reference.scheme)
+                    .is_some_and(|provider| provider.capabilities().sensitive);
+
+                for (name, value) in loaded {
+                    if sensitive {
+                        tracing::debug!("Loaded {} from {}", name, reference);
                     } else {
-                        passed_variables.insert(key, remainder.to_string());
-                    }
-                }
-                "aws_sm" => {
-                    // Load the value from AWS Secrets Manager
-
-                    match amazon.get_secret(remainder).await {
-                        Some(value) => {
-                            if let Some(prefix) = &application.env_prefix {
-                                if key.starts_with(prefix) {
-                                    passed_variables.insert(
-                                        key.strip_prefix(prefix).unwrap().to_string(),
-                                        value,
-                                    );
-                                } else {
-                                    passed_variables.insert(key, value);
-                                }
-                            } else {
-                                passed_variables.insert(key, value);
-                            }
-                        }
-                        None => {
-                            tracing::warn!(
-                                "Failed to load secret {} for variable {}",
-                                remainder,
-                                key
-                            );
-                            if !application.ignore_missing {
-                                std::process::exit(1);
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    tracing::warn!("Unknown load method {} for variable {}", load_method, key);
-                    if !application.ignore_missing {
-                        std::process::exit(1);
+                        tracing::debug!("Loaded {} = {}", name, value);
                     }
+
+                    passed_variables.insert(name, value);
                 }
             }
+            Resolution::Default(default) => {
+                passed_variables.insert(request.name.clone(), default.to_string());
+            }
+            Resolution::Failed(reference, error)
+                if tolerated(&error, request.required, application.ignore_missing) =>
+            {
+                tracing::warn!(
+                    "Failed to load {} for variable {}: {}",
+                    reference,
+                    request.key,
+                    error
+                );
+            }
+            Resolution::Failed(reference, error) => {
+                tracing::error!(
+                    "Failed to load {} for variable {}: {}",
+                    reference,
+                    request.key,
+                    error
+                );
+                failed.push(request.key.clone());
+            }
+        }
+    }
+
+    // Templates may refer to any loaded or passed-through variable
+    let templates = requests
+        .iter()
+        .filter_map(|request| match &request.source {
+            Source::Template(template) => Some((request.name.clone(), template.clone())),
+            Source::References(_) => None,
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut rendered = template::render(&templates, &passed_variables);
+
+    for request in &requests {
+        let Some(result) = rendered.remove(&request.name) else {
+            continue;
+        };
+
+        match result.and_then(|value| request.transform(value)) {
+            Ok(value) => {
+                tracing::debug!("Rendered {} from a template", request.name);
+                passed_variables.insert(request.name.clone(), value);
+            }
+            Err(error) if tolerated(&error, request.required, application.ignore_missing) => {
+                tracing::warn!(
+                    "Failed to render the template for variable {}: {}",
+                    request.key,
+                    error
+                );
+            }
+            Err(error) => {
+                tracing::error!(
+                    "Failed to render the template for variable {}: {}",
+                    request.key,
+                    error
+                );
+                failed.push(request.key.clone());
+            }
         }
     }
 
+    if !failed.is_empty() {
+        tracing::error!("{}", failure_summary(failed));
+        std::process::exit(1);
+    }
+
+    if application.supervise {
+        let code = supervise::run(
+            &registry,
+            &application.cmd,
+            &passed_variables,
+            application.call_timeout,
+        )
+        .await;
+        std::process::exit(code);
+    }
+
+    for (_, lease) in registry.leases() {
+        tracing::info!(
+            "Lease {} will not be renewed or revoked, run with --supervise to manage it",
+            lease.id
+        );
+    }
+
     // Go ahead and call the target application,
 
     let binary = std::ffi::CString::from_str(&application.cmd[0]).unwrap();
@@ -203,3 +810,140 @@ async fn main() {
 
     nix::unistd::execvpe(&binary, &args, &env).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> Registry {
+        providers::registry(&Application::parse_from(["environment-loader", "true"]))
+    }
+
+    #[test]
+    fn marks_variables_optional() {
+        let mut registry = registry();
+        let mut optional = |value| parse_source(&mut registry, value).map(|(_, optional)| optional);
+
+        assert_eq!(optional("file::/run/secrets/db"), Some(false));
+        assert_eq!(optional("file?::/run/secrets/db"), Some(true));
+        assert_eq!(
+            optional("file::/run/secrets/db || value?::fallback"),
+            Some(true)
+        );
+        assert_eq!(optional("template?::${HOST}"), Some(true));
+        assert_eq!(optional("not a reference"), None);
+    }
+
+    #[test]
+    fn tolerates_only_missing_values() {
+        let mut registry = registry();
+        let Some((Err(unknown), true)) = parse_source(&mut registry, "no-such-scheme?::x") else {
+            panic!("expected an optional unknown scheme");
+        };
+        let denied = ProviderError::AccessDenied("AccessDeniedException".to_string());
+        let invalid = ProviderError::Invalid("field password not present".to_string());
+
+        // An unknown scheme counts as missing
+        assert!(tolerated(&unknown, false, false));
+        assert!(tolerated(&unknown, true, true));
+        assert!(!tolerated(&unknown, true, false));
+
+        for error in [denied, invalid] {
+            assert!(!tolerated(&error, false, false));
+            assert!(!tolerated(&error, false, true));
+        }
+    }
+
+    #[test]
+    fn summarises_failures_in_name_order() {
+        assert_eq!(
+            failure_summary(vec!["DB_PASSWORD".to_string(), "API_KEY".to_string()]),
+            "2 required variables could not be loaded: API_KEY, DB_PASSWORD"
+        );
+        assert_eq!(
+            failure_summary(vec!["DB_PASSWORD".to_string()]),
+            "1 required variable could not be loaded: DB_PASSWORD"
+        );
+    }
+
+    fn manifest(settings: &str) -> Manifest {
+        toml::from_str(&format!("[settings]\n{settings}")).unwrap()
+    }
+
+    fn settings_args(settings: &str) -> Result<Vec<String>, String> {
+        manifest(settings)
+            .settings_args(&Application::command())
+            .map(|args| {
+                args.into_iter()
+                    .map(|arg| arg.into_string().unwrap())
+                    .collect()
+            })
+            .map_err(|error| error.to_string())
+    }
+
+    fn parse(settings: &str, args: &[&str]) -> Application {
+        let args = ["environment-loader"]
+            .iter()
+            .chain(args)
+            .map(OsString::from);
+        Application::parse_with_settings(&manifest(settings), args).unwrap()
+    }
+
+    #[test]
+    fn converts_settings_to_arguments() {
+        assert_eq!(
+            settings_args(
+                "allow_exec = true\nignore_missing = false\nmax_attempts = 5\npass = [\"HOME\", \"PATH\"]"
+            )
+            .unwrap(),
+            ["--allow-exec", "--max-attempts=5", "--pass=HOME", "--pass=PATH"]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for settings in [
+            "no_such_option = 1",
+            "config = \"other.toml\"",
+            "profile = \"prod\"",
+        ] {
+            assert!(
+                settings_args(settings)
+                    .unwrap_err()
+                    .contains("unknown setting")
+            );
+        }
+        assert!(
+            settings_args("allow_exec = \"yes\"")
+                .unwrap_err()
+                .contains("must be true or false")
+        );
+        assert!(settings_args("env_prefix = { a = 1 }").is_err());
+    }
+
+    #[test]
+    fn applies_settings_underneath_the_command_line() {
+        let settings = "max_attempts = 5\nconcurrency = 2\nallow_exec = true\npass = [\"HOME\"]";
+
+        let application = parse(settings, &["--max-attempts", "7", "--pass", "PATH", "true"]);
+        assert_eq!(application.max_attempts.get(), 7);
+        assert_eq!(application.concurrency.get(), 2);
+        assert!(application.allow_exec);
+        assert_eq!(application.pass, ["HOME", "PATH"]);
+        assert_eq!(application.cmd, ["true"]);
+
+        // Settings are given as arguments, so they override environment variables
+        unsafe { std::env::set_var("VAULT_NAMESPACE", "from-environment") };
+        let application = parse("vault_namespace = \"from-settings\"", &["true"]);
+        assert_eq!(
+            application.vault_namespace.as_deref(),
+            Some("from-settings")
+        );
+        let application = parse("", &["true"]);
+        assert_eq!(
+            application.vault_namespace.as_deref(),
+            Some("from-environment")
+        );
+        unsafe { std::env::remove_var("VAULT_NAMESPACE") };
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::provider::ProviderError;
+use crate::reference::interpolate;
+use crate::template::TEMPLATE_SCHEME;
+
+///
+/// A manifest given with `--config`, declaring the variables to load and
+/// defaults for the command-line options.
+///
+/// ```toml
+/// [settings]
+/// env_prefix = "MYAPP_"
+/// pass = ["PATH", "HOME"]
+///
+/// [placeholders]
+/// stage = "dev"
+///
+/// [variables]
+/// API_URL = "value::https://api.example.com"
+/// DB_PASSWORD = { source = "aws_sm::${stage}/db#password", transforms = ["trim"] }
+///
+/// [profiles.prod.placeholders]
+/// stage = "prod"
+/// ```
+///
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    ///
+    /// Command-line options by their long name with `_` for `-`, applied as
+    /// if given before the options on the command line.
+    ///
+    #[serde(default)]
+    pub settings: toml::Table,
+
+    ///
+    /// Values for `${name}` placeholders in variable sources.
+    ///
+    #[serde(default)]
+    pub placeholders: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub variables: BTreeMap<String, Variable>,
+
+    ///
+    /// Named overrides, one of which is selected with `--profile`.
+    ///
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+///
+/// Settings, placeholders and variables that replace the manifest's own
+/// entries of the same name when the profile is selected.
+///
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub settings: toml::Table,
+    #[serde(default)]
+    pub placeholders: BTreeMap<String, String>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, Variable>,
+}
+
+///
+/// A variable declared in the manifest, either as a bare source reference or
+/// as a table.
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "VariableEntry")]
+pub struct Variable {
+    pub source: String,
+
+    ///
+    /// Whether loading fails when the source is not found. Other errors
+    /// always fail.
+    ///
+    pub required: bool,
+
+    ///
+    /// Value used, as is, when the source is not found.
+    ///
+    pub default: Option<String>,
+
+    ///
+    /// Applied in order to every value loaded from the source.
+    ///
+    pub transforms: Vec<Transform>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VariableEntry {
+    Source(String),
+    Table(VariableTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VariableTable {
+    source: String,
+    #[serde(default = "required_by_default")]
+    required: bool,
+    default: Option<String>,
+    #[serde(default)]
+    transforms: Vec<Transform>,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+impl From<VariableEntry> for Variable {
+    fn from(entry: VariableEntry) -> Self {
+        match entry {
+            VariableEntry::Source(source) => Variable {
+                source,
+                required: true,
+                default: None,
+                transforms: Vec::new(),
+            },
+            VariableEntry::Table(table) => Variable {
+                source: table.source,
+                required: table.required,
+                default: table.default,
+                transforms: table.transforms,
+            },
+        }
+    }
+}
+
+///
+/// A conversion applied to a loaded value before it is exported.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    ///
+    /// Remove leading and trailing whitespace.
+    ///
+    Trim,
+    Lowercase,
+    Uppercase,
+
+    ///
+    /// Decode a base64 value, which must decode to UTF-8 text.
+    ///
+    Base64Decode,
+    Base64Encode,
+}
+
+impl Transform {
+    pub fn apply(self, value: String) -> Result<String, ProviderError> {
+        let engine = base64::engine::general_purpose::STANDARD;
+
+        match self {
+            Transform::Trim => Ok(value.trim().to_string()),
+            Transform::Lowercase => Ok(value.to_lowercase()),
+            Transform::Uppercase => Ok(value.to_uppercase()),
+            Transform::Base64Decode => engine
+                .decode(value.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(|| {
+                    ProviderError::Invalid(
+                        "base64_decode: value is not base64-encoded UTF-8 text".to_string(),
+                    )
+                }),
+            Transform::Base64Encode => Ok(engine.encode(value)),
+        }
+    }
+}
+
+///
+/// Why a manifest could not be used.
+///
+#[derive(Debug)]
+pub enum ManifestError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Setting(String),
+    Profile(String),
+    Placeholder(String, String),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Read(path, error) => {
+                write!(f, "cannot read manifest {}: {error}", path.display())
+            }
+            ManifestError::Parse(path, error) => {
+                write!(f, "invalid manifest {}: {error}", path.display())
+            }
+            ManifestError::Setting(message) => write!(f, "invalid manifest setting: {message}"),
+            ManifestError::Profile(name) => write!(f, "manifest has no profile {name}"),
+            ManifestError::Placeholder(variable, message) => {
+                write!(f, "invalid source for variable {variable}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| ManifestError::Read(path.to_path_buf(), error))?;
+
+        toml::from_str(&contents).map_err(|error| ManifestError::Parse(path.to_path_buf(), error))
+    }
+
+    ///
+    /// Apply the profile `name` over the manifest's own entries. The
+    /// `${profile}` placeholder is the profile's name unless it defines one.
+    ///
+    pub fn select_profile(&mut self, name: &str) -> Result<(), ManifestError> {
+        let Some(profile) = self.profiles.remove(name) else {
+            return Err(ManifestError::Profile(name.to_string()));
+        };
+
+        self.settings.extend(profile.settings);
+        self.placeholders
+            .insert("profile".to_string(), name.to_string());
+        self.placeholders.extend(profile.placeholders);
+        self.variables.extend(profile.variables);
+
+        Ok(())
+    }
+
+    ///
+    /// Replace the `${name}` placeholders in every variable's source, except
+    /// templates, whose `${name}`s refer to variables.
+    ///
+    pub fn substitute_placeholders(&mut self) -> Result<(), ManifestError> {
+        for (name, variable) in &mut self.variables {
+            if variable
+                .source
+                .strip_prefix(TEMPLATE_SCHEME)
+                .is_some_and(|rest| rest.starts_with("::"))
+            {
+                continue;
+            }
+
+            variable.source = interpolate(&variable.source, |placeholder| {
+                self.placeholders.get(placeholder).cloned()
+            })
+            .map_err(|message| ManifestError::Placeholder(name.clone(), message))?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// The `[settings]` table as command-line arguments for `command`.
+    ///
+    /// Flags are set with `true` and left unset with `false`; arrays give
+    /// an option once per element.
+    ///
+    pub fn settings_args(&self, command: &clap::Command) -> Result<Vec<OsString>, ManifestError> {
+        let mut args = Vec::new();
+
+        for (key, value) in &self.settings {
+            let Some((arg, long)) = command
+                .get_arguments()
+                .filter(|arg| arg.get_id() == key.as_str() && key != "config" && key != "profile")
+                .find_map(|arg| Some((arg, arg.get_long()?)))
+            else {
+                return Err(ManifestError::Setting(format!("unknown setting {key}")));
+            };
+
+            if !arg.get_action().takes_values() {
+                match value {
+                    toml::Value::Boolean(true) => args.push(format!("--{long}").into()),
+                    toml::Value::Boolean(false) => {}
+                    _ => {
+                        return Err(ManifestError::Setting(format!(
+                            "{key} must be true or false"
+                        )));
+                    }
+                }
+                continue;
+            }
+
+            let values = match value {
+                toml::Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+
+            for value in values {
+                let value = match value {
+                    toml::Value::String(value) => value.clone(),
+                    toml::Value::Integer(value) => value.to_string(),
+                    toml::Value::Float(value) => value.to_string(),
+                    toml::Value::Boolean(value) => value.to_string(),
+                    _ => {
+                        return Err(ManifestError::Setting(format!(
+                            "{key} must be a string, number or boolean"
+                        )));
+                    }
+                };
+                args.push(format!("--{long}={value}").into());
+            }
+        }
+
+        Ok(args)
+    }
+}
@@ -0,0 +1,732 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use futures::StreamExt;
+
+use crate::reference::{Locator, Reference};
+use crate::retry::RetryPolicy;
+use crate::secret::Secret;
+
+///
+/// Static properties of a provider that the loader uses to decide how to
+/// treat the values it returns.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Capabilities {
+    ///
+    /// Values returned by this provider are secrets and must never be logged.
+    ///
+    pub sensitive: bool,
+
+    ///
+    /// Values may be JSON documents, so references accept a `#field`
+    /// suffix selecting a single field.
+    ///
+    pub json_fields: bool,
+
+    ///
+    /// Maximum number of locators the provider can resolve in one
+    /// `resolve_batch` call, if it supports batching at all.
+    ///
+    pub batch: Option<usize>,
+
+    ///
+    /// Values may be binary, so references accept a `binary` option choosing
+    /// how they are exposed.
+    ///
+    pub binary: bool,
+
+    ///
+    /// Resolving the same locator twice has no side effects. Calls to
+    /// providers that issue something new on every call, such as dynamic
+    /// credentials, are made only once, since a repeated call could issue
+    /// credentials that are never recorded or revoked.
+    ///
+    pub idempotent: bool,
+}
+
+///
+/// Why a provider failed to load a value.
+///
+/// Messages from remote services include the service's error code, so the
+/// log shows exactly what went wrong.
+///
+#[derive(Debug, Clone)]
+pub enum ProviderError {
+    ///
+    /// The referenced value does not exist. This is the only error
+    /// `--ignore-missing` tolerates.
+    ///
+    NotFound(String),
+
+    ///
+    /// The reference is malformed, or the value was loaded but does not
+    /// match what the reference asked for.
+    ///
+    Invalid(String),
+
+    ///
+    /// The credentials were missing or rejected, or do not grant access to
+    /// the value.
+    ///
+    AccessDenied(String),
+
+    ///
+    /// The value exists but could not be decrypted (for example a KMS key
+    /// the credentials cannot use).
+    ///
+    Decryption(String),
+
+    ///
+    /// The service rejected the request because of rate limiting.
+    ///
+    Throttled(String),
+
+    ///
+    /// The service could not be reached or failed to handle the request
+    /// (connection failures, timeouts, internal server errors).
+    ///
+    Transport(String),
+
+    ///
+    /// Any other error reported by the service.
+    ///
+    Service(String),
+}
+
+impl ProviderError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ProviderError::NotFound(_))
+    }
+
+    ///
+    /// Whether trying the same call again may succeed.
+    ///
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ProviderError::Throttled(_) | ProviderError::Transport(_)
+        )
+    }
+
+    ///
+    /// Note in the message how many attempts were made before giving up.
+    ///
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        let (ProviderError::NotFound(message)
+        | ProviderError::Invalid(message)
+        | ProviderError::AccessDenied(message)
+        | ProviderError::Decryption(message)
+        | ProviderError::Throttled(message)
+        | ProviderError::Transport(message)
+        | ProviderError::Service(message)) = &mut self;
+        message.push_str(&format!(" (after {attempts} attempts)"));
+        self
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::NotFound(message) => write!(f, "not found: {message}"),
+            ProviderError::Invalid(message) => write!(f, "invalid: {message}"),
+            ProviderError::AccessDenied(message) => write!(f, "access denied: {message}"),
+            ProviderError::Decryption(message) => write!(f, "decryption failed: {message}"),
+            ProviderError::Throttled(message) => write!(f, "throttled: {message}"),
+            ProviderError::Transport(message) => write!(f, "transport error: {message}"),
+            ProviderError::Service(message) => write!(f, "service error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+///
+/// A time-limited grant attached to a value, such as the lease on Vault
+/// dynamic database credentials. The value stops working once the lease
+/// expires or is revoked.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub id: String,
+
+    ///
+    /// Time left on the lease when it was issued or last renewed.
+    ///
+    pub duration: Duration,
+    pub renewable: bool,
+}
+
+///
+/// A source of variable values, selected by the scheme in front of `::`
+/// in an environment value (`aws_sm::my-secret` is handled by the provider
+/// whose scheme is `aws_sm`).
+///
+#[async_trait::async_trait]
+pub trait Provider: Send + Sync {
+    ///
+    /// The scheme this provider is registered under.
+    ///
+    fn scheme(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    ///
+    /// Names of the `?key=value` options this provider understands.
+    ///
+    /// References to providers without options are never split at `?`.
+    ///
+    fn options(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    ///
+    /// Whether references accept a `#field` suffix.
+    ///
+    /// Providers whose paths are free-form text, such as shell commands,
+    /// turn this off so that a `#` is always part of the path.
+    ///
+    fn field_suffix(&self) -> bool {
+        self.capabilities().json_fields
+    }
+
+    ///
+    /// Resolve the part of the value that follows `scheme::`.
+    ///
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError>;
+
+    ///
+    /// Resolve several locators at once, returning one result per locator
+    /// in the same order.
+    ///
+    /// Only called for providers that advertise `Capabilities::batch`, with
+    /// at most that many locators.
+    ///
+    async fn resolve_batch(&self, locators: &[&Locator]) -> Vec<Result<Secret, ProviderError>> {
+        let mut results = Vec::with_capacity(locators.len());
+        for locator in locators {
+            results.push(self.resolve(locator).await);
+        }
+        results
+    }
+
+    ///
+    /// Leases on the values this provider has resolved so far.
+    ///
+    fn leases(&self) -> Vec<Lease> {
+        Vec::new()
+    }
+
+    ///
+    /// Extend a lease returned by `leases`, returning it with its new duration.
+    ///
+    async fn renew_lease(&self, lease: &Lease) -> Result<Lease, ProviderError> {
+        Err(ProviderError::Invalid(format!(
+            "{} cannot renew lease {}",
+            self.scheme(),
+            lease.id
+        )))
+    }
+
+    ///
+    /// Revoke a lease returned by `leases`, invalidating its value.
+    ///
+    async fn revoke_lease(&self, lease: &Lease) -> Result<(), ProviderError> {
+        Err(ProviderError::Invalid(format!(
+            "{} cannot revoke lease {}",
+            self.scheme(),
+            lease.id
+        )))
+    }
+}
+
+///
+/// A variable's fallback chain (`a::x || b::y`), tried from left to right.
+///
+pub struct Chain<'a> {
+    ///
+    /// The variable the chain came from, for log messages.
+    ///
+    pub key: &'a str,
+    pub references: &'a [Reference],
+
+    ///
+    /// Value used when the last reference is not found.
+    ///
+    pub default: Option<&'a str>,
+}
+
+///
+/// How a fallback chain was resolved, with the reference that loaded or, if
+/// none did, the last one tried.
+///
+#[derive(Debug)]
+pub enum Resolution<'a, T> {
+    Loaded(&'a Reference, T),
+
+    ///
+    /// A reference was not found, and its own or the chain's default is
+    /// used instead.
+    ///
+    Default(&'a str),
+    Failed(&'a Reference, ProviderError),
+}
+
+///
+/// The set of providers available to the loader, keyed by scheme.
+///
+#[derive(Default)]
+pub struct Registry {
+    providers: HashMap<&'static str, Box<dyn Provider>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, provider: impl Provider + 'static) {
+        let scheme = provider.scheme();
+        if self.providers.insert(scheme, Box::new(provider)).is_some() {
+            tracing::warn!("Provider for scheme {} registered twice", scheme);
+        }
+    }
+
+    pub fn get(&self, scheme: &str) -> Option<&dyn Provider> {
+        self.providers.get(scheme).map(|provider| provider.as_ref())
+    }
+
+    ///
+    /// Every lease held on a resolved value, with the provider that issued it.
+    ///
+    pub fn leases(&self) -> Vec<(&dyn Provider, Lease)> {
+        self.providers
+            .values()
+            .flat_map(|provider| {
+                provider
+                    .leases()
+                    .into_iter()
+                    .map(|lease| (provider.as_ref(), lease))
+            })
+            .collect()
+    }
+
+    ///
+    /// Resolve every reference, running at most `concurrency` provider calls
+    /// at a time, each retried according to `policy`.
+    ///
+    /// References that share a scheme and locator are fetched once, whatever
+    /// field they select, and providers that support batching receive their
+    /// locators in batches. Results are returned in the order of `references`,
+    /// regardless of the order in which the calls complete.
+    ///
+    pub async fn resolve_all(
+        &self,
+        references: &[&Reference],
+        concurrency: NonZeroUsize,
+        policy: &RetryPolicy,
+    ) -> Vec<Result<Secret, ProviderError>> {
+        let mut by_scheme = HashMap::<&str, Vec<&Locator>>::new();
+        for reference in references {
+            by_scheme
+                .entry(reference.scheme.as_str())
+                .or_default()
+                .push(&reference.locator);
+        }
+
+        let mut calls = Vec::new();
+        for (scheme, mut locators) in by_scheme {
+            locators.sort_unstable();
+            locators.dedup();
+
+            let Some(provider) = self.get(scheme) else {
+                continue;
+            };
+
+            match provider.capabilities().batch {
+                Some(size) if size > 1 => calls.extend(
+                    locators
+                        .chunks(size)
+                        .map(|chunk| (scheme, provider, chunk.to_vec())),
+                ),
+                _ => calls.extend(
+                    locators
+                        .into_iter()
+                        .map(|locator| (scheme, provider, vec![locator])),
+                ),
+            }
+        }
+
+        let fetched = futures::stream::iter(calls)
+            .map(|(scheme, provider, locators)| async move {
+                let results = policy.resolve(provider, &locators).await;
+
+                locators
+                    .into_iter()
+                    .zip(results)
+                    .map(|(locator, result)| ((scheme, locator), result))
+                    .collect::<Vec<_>>()
+            })
+            .buffer_unordered(concurrency.get())
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .flatten()
+            .collect::<HashMap<_, _>>();
+
+        references
+            .iter()
+            .map(|reference| {
+                fetched
+                    .get(&(reference.scheme.as_str(), &reference.locator))
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        Err(ProviderError::NotFound(format!(
+                            "no provider for {}",
+                            reference.scheme
+                        )))
+                    })
+            })
+            .collect()
+    }
+
+    ///
+    /// Resolve every chain, loading each successfully fetched value with
+    /// `load` (given the chain's index).
+    ///
+    /// Each round resolves the next reference of every chain whose previous
+    /// reference failed, so fallbacks are only fetched when needed. A
+    /// reference that is not found ends its chain if it has a default, as
+    /// does the last reference if the chain has one. Locators fetched in an
+    /// earlier round are not fetched again: dynamic secrets issue new
+    /// credentials on every read. Resolutions are returned in the order of
+    /// `chains`.
+    ///
+    pub async fn resolve_chains<'a, T>(
+        &self,
+        chains: &[Chain<'a>],
+        concurrency: NonZeroUsize,
+        policy: &RetryPolicy,
+        mut load: impl FnMut(usize, &Reference, Secret) -> Result<T, ProviderError>,
+    ) -> Vec<Resolution<'a, T>> {
+        let mut resolutions = chains.iter().map(|_| None).collect::<Vec<_>>();
+        let mut fetched = HashMap::<(&str, &Locator), Result<Secret, ProviderError>>::new();
+
+        // The chain and the position of the reference to try next
+        let mut pending = (0..chains.len())
+            .map(|index| (index, 0))
+            .collect::<Vec<_>>();
+
+        while !pending.is_empty() {
+            let unfetched = pending
+                .iter()
+                .map(|&(index, position)| &chains[index].references[position])
+                .filter(|reference| {
+                    !fetched.contains_key(&(reference.scheme.as_str(), &reference.locator))
+                })
+                .collect::<Vec<_>>();
+
+            let results = self.resolve_all(&unfetched, concurrency, policy).await;
+            for (reference, result) in unfetched.into_iter().zip(results) {
+                fetched.insert((reference.scheme.as_str(), &reference.locator), result);
+            }
+
+            let mut fallbacks = Vec::new();
+
+            for (index, position) in pending {
+                let chain = &chains[index];
+                let reference = &chain.references[position];
+                let next = chain.references.get(position + 1);
+
+                let error = match fetched[&(reference.scheme.as_str(), &reference.locator)]
+                    .clone()
+                    .and_then(|secret| load(index, reference, secret))
+                {
+                    Ok(loaded) => {
+                        resolutions[index] = Some(Resolution::Loaded(reference, loaded));
+                        continue;
+                    }
+                    Err(error) => error,
+                };
+
+                let default = match next {
+                    Some(_) => reference.default.as_deref(),
+                    None => reference.default.as_deref().or(chain.default),
+                };
+
+                resolutions[index] = match (default, next) {
+                    (Some(default), _) if error.is_not_found() => {
+                        tracing::warn!(
+                            "Failed to load {} for variable {}: {}, using the default",
+                            reference,
+                            chain.key,
+                            error
+                        );
+                        Some(Resolution::Default(default))
+                    }
+                    (_, Some(next)) => {
+                        tracing::warn!(
+                            "Failed to load {} for variable {}: {}, falling back to {}",
+                            reference,
+                            chain.key,
+                            error,
+                            next
+                        );
+                        fallbacks.push((index, position + 1));
+                        None
+                    }
+                    _ => Some(Resolution::Failed(reference, error)),
+                };
+            }
+
+            pending = fallbacks;
+        }
+
+        resolutions.into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    ///
+    /// Loads every path as its own value, except that paths starting with
+    /// `missing` are not found and paths starting with `denied` are denied.
+    /// Records the paths of every call.
+    ///
+    struct Counting {
+        batch: Option<usize>,
+        calls: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for Counting {
+        fn scheme(&self) -> &'static str {
+            "stub"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                json_fields: true,
+                batch: self.batch,
+                idempotent: true,
+                ..Capabilities::default()
+            }
+        }
+
+        fn options(&self) -> &'static [&'static str] {
+            &["version"]
+        }
+
+        async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+            self.resolve_batch(&[locator]).await.remove(0)
+        }
+
+        async fn resolve_batch(&self, locators: &[&Locator]) -> Vec<Result<Secret, ProviderError>> {
+            let paths = locators.iter().map(|locator| locator.path.clone());
+            self.calls.lock().unwrap().push(paths.collect());
+
+            locators
+                .iter()
+                .map(|locator| match locator.path.as_str() {
+                    path if path.starts_with("missing") => {
+                        Err(ProviderError::NotFound(path.to_string()))
+                    }
+                    path if path.starts_with("denied") => {
+                        Err(ProviderError::AccessDenied(path.to_string()))
+                    }
+                    path => Ok(Secret::Text(path.to_string())),
+                })
+                .collect()
+        }
+    }
+
+    fn registry(batch: Option<usize>) -> (Registry, Arc<Mutex<Vec<Vec<String>>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = Registry::new();
+        registry.register(Counting {
+            batch,
+            calls: calls.clone(),
+        });
+        (registry, calls)
+    }
+
+    fn parse_references(registry: &Registry, references: &str) -> Vec<Reference> {
+        let provider = registry.get("stub").unwrap();
+        references
+            .split(" || ")
+            .map(|reference| Reference::parse("stub", reference, provider, false).unwrap())
+            .collect()
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(1).unwrap(),
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            jitter: 0.0,
+            call_timeout: Duration::from_secs(5),
+            deadline: None,
+        }
+    }
+
+    async fn resolve_chains(registry: &Registry, chains: &[(&str, Option<&str>)]) -> Vec<String> {
+        let references = chains
+            .iter()
+            .map(|(references, _)| parse_references(registry, references))
+            .collect::<Vec<_>>();
+        let chains = references
+            .iter()
+            .zip(chains)
+            .map(|(references, (_, default))| Chain {
+                key: "VARIABLE",
+                references,
+                default: *default,
+            })
+            .collect::<Vec<_>>();
+
+        let concurrency = NonZeroUsize::new(4).unwrap();
+        let load = |_, reference: &Reference, secret: Secret| reference.select(secret.into_text()?);
+
+        registry
+            .resolve_chains(&chains, concurrency, &policy(), load)
+            .await
+            .into_iter()
+            .map(|resolution| match resolution {
+                Resolution::Loaded(reference, value) => format!("{reference} = {value}"),
+                Resolution::Default(default) => format!("default {default}"),
+                Resolution::Failed(reference, error) => format!("{reference}: {error}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn falls_back_from_left_to_right() {
+        let (registry, calls) = registry(None);
+
+        let resolved = resolve_chains(
+            &registry,
+            &[
+                ("missing || denied || b || c", None),
+                ("a#field || d", None),
+                ("missing || denied", None),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            resolved,
+            [
+                "stub::b = b",
+                "stub::d = d",
+                "stub::denied: access denied: denied",
+            ]
+        );
+        let mut calls = calls.lock().unwrap().concat();
+        calls.sort();
+        assert_eq!(calls, ["a", "b", "d", "denied", "missing"]);
+    }
+
+    #[tokio::test]
+    async fn uses_defaults_only_for_values_that_are_not_found() {
+        let (registry, calls) = registry(None);
+
+        let resolved = resolve_chains(
+            &registry,
+            &[
+                ("missing-1?default=x || a", None),
+                ("denied-1?default=x || b", None),
+                ("missing-2 || missing-3", Some("y")),
+                ("missing-4 || denied-2", Some("y")),
+                ("missing-5?default=x", Some("y")),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            resolved,
+            [
+                "default x",
+                "stub::b = b",
+                "default y",
+                "stub::denied-2: access denied: denied-2",
+                "default x",
+            ]
+        );
+        // A default ends the chain, so `a` is never fetched
+        assert!(!calls.lock().unwrap().concat().contains(&"a".to_string()));
+    }
+
+    #[tokio::test]
+    async fn fetches_each_locator_once_across_rounds() {
+        let (registry, calls) = registry(None);
+
+        let resolved = resolve_chains(
+            &registry,
+            &[
+                ("missing || creds", None),
+                ("creds", None),
+                ("denied || missing || creds", None),
+            ],
+        )
+        .await;
+
+        assert!(
+            resolved
+                .iter()
+                .all(|resolved| resolved == "stub::creds = creds")
+        );
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [vec!["creds"], vec!["denied"], vec!["missing"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_shared_locators_once_in_reference_order() {
+        let (registry, calls) = registry(None);
+        let references = parse_references(&registry, "c#user || a || c#password || b || a");
+
+        let results = registry
+            .resolve_all(
+                &references.iter().collect::<Vec<_>>(),
+                NonZeroUsize::new(2).unwrap(),
+                &policy(),
+            )
+            .await;
+
+        let values = results
+            .into_iter()
+            .map(|result| result.unwrap().into_text().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, ["c", "a", "c", "b", "a"]);
+        assert_eq!(calls.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn batches_locators_up_to_the_batch_size() {
+        let (registry, calls) = registry(Some(2));
+        let references = parse_references(&registry, "e || d || c || b || a || a");
+
+        let results = registry
+            .resolve_all(
+                &references.iter().collect::<Vec<_>>(),
+                NonZeroUsize::new(1).unwrap(),
+                &policy(),
+            )
+            .await;
+
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[0].clone().unwrap(), Secret::Text("e".to_string()));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [vec!["a", "b"], vec!["c", "d"], vec!["e"]]
+        );
+    }
+}
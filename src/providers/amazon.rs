@@ -0,0 +1,635 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use aws_config::Region;
+use aws_config::sts::AssumeRoleProvider;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_credential_types::provider::error::CredentialsError;
+use aws_sdk_secretsmanager::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_secretsmanager::types::{ApiErrorType, SecretValueEntry};
+use tokio::sync::Mutex;
+
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+///
+/// Maximum number of secret IDs accepted by a single `BatchGetSecretValue` call.
+///
+const SECRETS_BATCH_LIMIT: usize = 20;
+
+///
+/// The IAM role assumed before any AWS call is made, from `--assume-role`.
+///
+#[derive(Debug, Clone, Default)]
+pub struct AssumeRole {
+    ///
+    /// Role assumed for every reference without its own `role` option.
+    ///
+    pub role_arn: Option<String>,
+    pub external_id: Option<String>,
+    pub session_name: String,
+}
+
+///
+/// Which AWS client a reference needs, selected with the `region`,
+/// `profile` and `role` options or the region embedded in an ARN.
+///
+/// `None` means the default: the standard AWS configuration chain for the
+/// region and profile, and `--assume-role` (if given) for the role.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ClientKey {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    pub role: Option<String>,
+}
+
+impl ClientKey {
+    pub fn from_locator(locator: &Locator) -> Result<Self, ProviderError> {
+        for option in ["region", "profile", "role"] {
+            if locator.option(option) == Some("") {
+                return Err(ProviderError::Invalid(format!(
+                    "{} has an empty {option}",
+                    locator.path
+                )));
+            }
+        }
+
+        let region = match (locator.option("region"), arn_region(&locator.path)) {
+            (Some(region), Some(arn_region)) if region != arn_region => {
+                return Err(ProviderError::Invalid(format!(
+                    "region {region} does not match the region of ARN {}",
+                    locator.path
+                )));
+            }
+            (Some(region), _) | (None, Some(region)) => Some(region.to_string()),
+            (None, None) => None,
+        };
+
+        Ok(Self {
+            region,
+            profile: locator.option("profile").map(String::from),
+            role: locator.option("role").map(String::from),
+        })
+    }
+}
+
+///
+/// The region of an `arn:<partition>:<service>:<region>:...` identifier.
+///
+fn arn_region(path: &str) -> Option<&str> {
+    if !path.starts_with("arn:") {
+        return None;
+    }
+
+    path.split(':').nth(3).filter(|region| !region.is_empty())
+}
+
+///
+/// Shared AWS state for the AWS-backed providers.
+///
+/// The SDK configuration and clients are built lazily on first use, so no
+/// AWS credentials are needed unless an AWS reference is present. One
+/// configuration is loaded per profile, one set of assumed-role credentials
+/// is kept per profile and role, and one client is kept per `ClientKey`.
+///
+#[derive(Default)]
+pub struct Amazon {
+    assume_role: AssumeRole,
+    configs: Mutex<HashMap<Option<String>, aws_config::SdkConfig>>,
+    role_configs: Mutex<HashMap<(Option<String>, String), aws_config::SdkConfig>>,
+    secrets_clients: Mutex<HashMap<ClientKey, aws_sdk_secretsmanager::Client>>,
+    ssm_clients: Mutex<HashMap<ClientKey, aws_sdk_ssm::Client>>,
+}
+
+impl Amazon {
+    pub fn new(assume_role: AssumeRole) -> Self {
+        Self {
+            assume_role,
+            ..Self::default()
+        }
+    }
+
+    ///
+    /// The SDK configuration for a named profile, or for the default
+    /// configuration chain when `profile` is `None`.
+    ///
+    pub async fn get_config(&self, profile: Option<&str>) -> aws_config::SdkConfig {
+        let mut configs = self.configs.lock().await;
+
+        if let Some(config) = configs.get(&profile.map(String::from)) {
+            return config.clone();
+        }
+
+        // Retries are handled uniformly for every provider by `RetryPolicy`
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::v2026_01_12())
+            .retry_config(aws_config::retry::RetryConfig::disabled());
+        if let Some(profile) = profile {
+            loader = loader.profile_name(profile);
+        }
+        let config = loader.load().await;
+
+        configs.insert(profile.map(String::from), config.clone());
+        config
+    }
+
+    ///
+    /// The configuration for a client: the profile's configuration from
+    /// `get_config`, with its credentials replaced by those of the assumed
+    /// role if one applies.
+    ///
+    async fn client_config(&self, key: &ClientKey) -> aws_config::SdkConfig {
+        let config = self.get_config(key.profile.as_deref()).await;
+
+        let Some(role_arn) = key.role.as_ref().or(self.assume_role.role_arn.as_ref()) else {
+            return config;
+        };
+
+        let mut role_configs = self.role_configs.lock().await;
+        let cache_key = (key.profile.clone(), role_arn.clone());
+
+        if let Some(config) = role_configs.get(&cache_key) {
+            return config.clone();
+        }
+
+        tracing::debug!("Assuming role {}", role_arn);
+
+        let mut provider = AssumeRoleProvider::builder(role_arn)
+            .session_name(&self.assume_role.session_name)
+            .configure(&config);
+        if let Some(external_id) = &self.assume_role.external_id {
+            provider = provider.external_id(external_id);
+        }
+        let provider = provider.build().await;
+
+        let config = config
+            .to_builder()
+            .credentials_provider(SharedCredentialsProvider::new(provider))
+            .build();
+
+        role_configs.insert(cache_key, config.clone());
+        config
+    }
+
+    async fn secrets_client(&self, key: &ClientKey) -> aws_sdk_secretsmanager::Client {
+        let mut clients = self.secrets_clients.lock().await;
+
+        if let Some(client) = clients.get(key) {
+            return client.clone();
+        }
+
+        let config = self.client_config(key).await;
+        let mut builder = aws_sdk_secretsmanager::config::Builder::from(&config);
+        if let Some(region) = &key.region {
+            builder = builder.region(Region::new(region.clone()));
+        }
+        let client = aws_sdk_secretsmanager::Client::from_conf(builder.build());
+
+        clients.insert(key.clone(), client.clone());
+        client
+    }
+
+    async fn ssm_client(&self, key: &ClientKey) -> aws_sdk_ssm::Client {
+        let mut clients = self.ssm_clients.lock().await;
+
+        if let Some(client) = clients.get(key) {
+            return client.clone();
+        }
+
+        let config = self.client_config(key).await;
+        let mut builder = aws_sdk_ssm::config::Builder::from(&config);
+        if let Some(region) = &key.region {
+            builder = builder.region(Region::new(region.clone()));
+        }
+        let client = aws_sdk_ssm::Client::from_conf(builder.build());
+
+        clients.insert(key.clone(), client.clone());
+        client
+    }
+
+    pub async fn get_secret(
+        &self,
+        key: &ClientKey,
+        secret_name: &str,
+        version: &SecretVersion,
+    ) -> Result<Secret, ProviderError> {
+        let request = self
+            .secrets_client(key)
+            .await
+            .get_secret_value()
+            .secret_id(secret_name);
+
+        let request = match version {
+            SecretVersion::Current => request,
+            SecretVersion::Stage(stage) => request.version_stage(stage),
+            SecretVersion::Id(version_id) => request.version_id(version_id),
+        };
+
+        let response = request.send().await.map_err(sdk_error)?;
+
+        secret_value(response.secret_string(), response.secret_binary())
+    }
+
+    ///
+    /// Fetch the current version of several secrets with `BatchGetSecretValue`,
+    /// returning one result per secret ID in the order given.
+    ///
+    /// Errors for individual secrets are returned in their slot; the outer
+    /// error means the batch call itself failed (for example when the role
+    /// lacks `secretsmanager:BatchGetSecretValue`).
+    ///
+    pub async fn get_secrets(
+        &self,
+        key: &ClientKey,
+        secret_names: &[&str],
+    ) -> Result<Vec<Result<Secret, ProviderError>>, ProviderError> {
+        let client = self.secrets_client(key).await;
+
+        let mut values = Vec::<SecretValueEntry>::new();
+        let mut errors = Vec::<ApiErrorType>::new();
+        let mut next_token = None;
+
+        loop {
+            let response = client
+                .batch_get_secret_value()
+                .set_secret_id_list(Some(secret_names.iter().map(|s| s.to_string()).collect()))
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(sdk_error)?;
+
+            values.extend_from_slice(response.secret_values());
+            errors.extend_from_slice(response.errors());
+
+            next_token = response.next_token().map(String::from);
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        let results = secret_names
+            .iter()
+            .map(|&secret_name| {
+                if let Some(entry) = values
+                    .iter()
+                    .find(|entry| secret_matches(secret_name, entry.name(), entry.arn()))
+                {
+                    secret_value(entry.secret_string(), entry.secret_binary())
+                } else if let Some(error) = errors
+                    .iter()
+                    .find(|error| error.secret_id() == Some(secret_name))
+                {
+                    Err(service_error(error.error_code(), error.message()))
+                } else {
+                    Err(ProviderError::NotFound(
+                        "secret missing from BatchGetSecretValue response".to_string(),
+                    ))
+                }
+            })
+            .collect();
+
+        Ok(results)
+    }
+
+    ///
+    /// Fetch a parameter from Systems Manager Parameter Store.
+    ///
+    /// `SecureString` parameters are decrypted.
+    ///
+    pub async fn get_parameter(
+        &self,
+        key: &ClientKey,
+        parameter_name: &str,
+    ) -> Result<String, ProviderError> {
+        let response = self
+            .ssm_client(key)
+            .await
+            .get_parameter()
+            .name(parameter_name)
+            .with_decryption(true)
+            .send()
+            .await
+            .map_err(sdk_error)?;
+
+        response
+            .parameter()
+            .and_then(|parameter| parameter.value())
+            .map(String::from)
+            .ok_or_else(|| ProviderError::NotFound("parameter has no value".to_string()))
+    }
+}
+
+///
+/// The value of a secret: its `SecretString`, or its `SecretBinary` if it has no string.
+///
+fn secret_value(
+    secret_string: Option<&str>,
+    secret_binary: Option<&aws_sdk_secretsmanager::primitives::Blob>,
+) -> Result<Secret, ProviderError> {
+    match (secret_string, secret_binary) {
+        (Some(string), _) => Ok(Secret::Text(string.to_string())),
+        (None, Some(binary)) => Ok(Secret::Binary(binary.as_ref().to_vec())),
+        (None, None) => Err(ProviderError::NotFound("secret has no value".to_string())),
+    }
+}
+
+///
+/// Classify an SDK error, keeping the service error code when there is one.
+///
+fn sdk_error<E, R>(error: SdkError<E, R>) -> ProviderError
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: std::fmt::Debug + 'static,
+{
+    if let SdkError::ServiceError(service) = &error {
+        return service_error(service.err().code(), service.err().message());
+    }
+
+    let credentials = std::iter::successors(
+        Some(&error as &(dyn std::error::Error + 'static)),
+        |error| error.source(),
+    )
+    .any(|error| error.is::<CredentialsError>());
+
+    let message = DisplayErrorContext(&error).to_string();
+
+    match error {
+        _ if credentials => ProviderError::AccessDenied(message),
+        SdkError::ConstructionFailure(_) => ProviderError::Invalid(message),
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            ProviderError::Transport(message)
+        }
+        _ => ProviderError::Service(message),
+    }
+}
+
+///
+/// Classify an error code returned by Secrets Manager, Parameter Store or STS.
+///
+fn service_error(code: Option<&str>, message: Option<&str>) -> ProviderError {
+    let code = code.unwrap_or("UnknownError");
+    let message = format!("{code}: {}", message.unwrap_or("no message"));
+
+    match code {
+        "ResourceNotFoundException" | "ParameterNotFound" | "ParameterVersionNotFound" => {
+            ProviderError::NotFound(message)
+        }
+        "AccessDeniedException"
+        | "AccessDenied"
+        | "UnrecognizedClientException"
+        | "InvalidClientTokenId"
+        | "InvalidSignatureException"
+        | "SignatureDoesNotMatch"
+        | "ExpiredTokenException"
+        | "ExpiredToken" => ProviderError::AccessDenied(message),
+        "DecryptionFailure" | "InvalidKeyId" => ProviderError::Decryption(message),
+        "ThrottlingException" | "Throttling" | "TooManyRequestsException" => {
+            ProviderError::Throttled(message)
+        }
+        "InternalServiceError"
+        | "InternalServerError"
+        | "InternalFailure"
+        | "ServiceUnavailable" => ProviderError::Transport(message),
+        _ => ProviderError::Service(message),
+    }
+}
+
+///
+/// Which version of a secret to fetch, selected with the `stage` or
+/// `version` option of an `aws_sm::` reference.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretVersion {
+    ///
+    /// The version labelled `AWSCURRENT`.
+    ///
+    Current,
+
+    ///
+    /// The version carrying a staging label, such as `AWSPREVIOUS`.
+    ///
+    Stage(String),
+
+    ///
+    /// An explicit `VersionId`.
+    ///
+    Id(String),
+}
+
+impl SecretVersion {
+    pub fn from_locator(locator: &Locator) -> Result<Self, ProviderError> {
+        match (locator.option("stage"), locator.option("version")) {
+            (None, None) => Ok(SecretVersion::Current),
+            (Some(_), Some(_)) => Err(ProviderError::Invalid(format!(
+                "secret {} selects both a stage and a version, use only one",
+                locator.path
+            ))),
+            (Some(""), None) | (None, Some("")) => Err(ProviderError::Invalid(format!(
+                "secret {} has an empty stage or version",
+                locator.path
+            ))),
+            (Some(stage), None) => Ok(SecretVersion::Stage(stage.to_string())),
+            (None, Some(version_id)) => Ok(SecretVersion::Id(version_id.to_string())),
+        }
+    }
+}
+
+///
+/// Whether a secret ID as written in a reference (a name, full ARN or
+/// partial ARN without the random suffix) identifies the returned secret.
+///
+fn secret_matches(secret_id: &str, name: Option<&str>, arn: Option<&str>) -> bool {
+    if name == Some(secret_id) || arn == Some(secret_id) {
+        return true;
+    }
+
+    // Full ARNs end in `-` followed by six random characters
+    arn.and_then(|arn| arn.strip_prefix(secret_id))
+        .is_some_and(|suffix| suffix.len() == 7 && suffix.starts_with('-'))
+}
+
+///
+/// `aws_sm::<secret-id>[?options][#field]` - loads the value from AWS Secrets
+/// Manager.
+///
+/// The secret ID may be a name or an ARN. Options select the version
+/// (`stage` or `version`) and the client (`region`, `profile`, `role`).
+/// Secrets stored as `SecretBinary` are exposed according to the `binary`
+/// option.
+///
+pub struct SecretsManager(pub Arc<Amazon>);
+
+#[async_trait::async_trait]
+impl Provider for SecretsManager {
+    fn scheme(&self) -> &'static str {
+        "aws_sm"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: Some(SECRETS_BATCH_LIMIT),
+            binary: true,
+            idempotent: true,
+        }
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        &["stage", "version", "region", "profile", "role"]
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        let key = ClientKey::from_locator(locator)?;
+        let version = SecretVersion::from_locator(locator)?;
+
+        self.0.get_secret(&key, &locator.path, &version).await
+    }
+
+    async fn resolve_batch(&self, locators: &[&Locator]) -> Vec<Result<Secret, ProviderError>> {
+        // `BatchGetSecretValue` can only fetch the current version, and each
+        // call goes through a single client
+        let mut groups = HashMap::<ClientKey, Vec<usize>>::new();
+        for (index, locator) in locators.iter().enumerate() {
+            if let (Ok(key), Ok(SecretVersion::Current)) = (
+                ClientKey::from_locator(locator),
+                SecretVersion::from_locator(locator),
+            ) {
+                groups.entry(key).or_default().push(index);
+            }
+        }
+
+        let batches = futures::future::join_all(
+            groups
+                .into_iter()
+                .filter(|(_, indices)| indices.len() > 1)
+                .map(|(key, indices)| async move {
+                    let secret_names = indices
+                        .iter()
+                        .map(|&index| locators[index].path.as_str())
+                        .collect::<Vec<_>>();
+                    let batch = self.0.get_secrets(&key, &secret_names).await;
+                    (indices, batch)
+                }),
+        )
+        .await;
+
+        let mut results = vec![None; locators.len()];
+        for (indices, batch) in batches {
+            match batch {
+                Ok(batch) => {
+                    for (index, result) in indices.into_iter().zip(batch) {
+                        results[index] = Some(result);
+                    }
+                }
+                // Most likely the batch permission is missing
+                Err(error @ ProviderError::AccessDenied(_)) => tracing::debug!(
+                    "BatchGetSecretValue failed ({}), fetching {} secrets individually",
+                    error,
+                    indices.len()
+                ),
+                // Fetching individually would only add calls when throttled
+                Err(error) => {
+                    for index in indices {
+                        results[index] = Some(Err(error.clone()));
+                    }
+                }
+            }
+        }
+
+        let remaining = futures::future::join_all(
+            locators
+                .iter()
+                .zip(&results)
+                .filter(|(_, result)| result.is_none())
+                .map(|(locator, _)| self.resolve(locator)),
+        )
+        .await;
+
+        let mut remaining = remaining.into_iter();
+        for result in results.iter_mut().filter(|result| result.is_none()) {
+            *result = remaining.next();
+        }
+
+        results.into_iter().flatten().collect()
+    }
+}
+
+///
+/// `aws_ssm::<parameter-name>[?options][#field]` - loads the value from AWS
+/// Systems Manager Parameter Store.
+///
+/// The parameter may be a name or an ARN. Options select the client
+/// (`region`, `profile`, `role`).
+///
+pub struct ParameterStore(pub Arc<Amazon>);
+
+#[async_trait::async_trait]
+impl Provider for ParameterStore {
+    fn scheme(&self) -> &'static str {
+        "aws_ssm"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: false,
+            idempotent: true,
+        }
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        &["region", "profile", "role"]
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        let key = ClientKey::from_locator(locator)?;
+
+        self.0
+            .get_parameter(&key, &locator.path)
+            .await
+            .map(Secret::Text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_batch_results_to_secret_ids() {
+        let full_arn = "arn:aws:secretsmanager:eu-west-1:123456789012:secret:prod/db-AbCdEf";
+        let (name, arn) = (Some("prod/db"), Some(full_arn));
+
+        assert!(secret_matches("prod/db", name, arn));
+        assert!(secret_matches(full_arn, name, arn));
+        assert!(secret_matches(
+            "arn:aws:secretsmanager:eu-west-1:123456789012:secret:prod/db",
+            name,
+            arn
+        ));
+        assert!(!secret_matches("prod", name, arn));
+        assert!(!secret_matches(
+            "arn:aws:secretsmanager:eu-west-1:123456789012:secret:prod",
+            name,
+            arn
+        ));
+    }
+
+    #[test]
+    fn reads_region_from_arns() {
+        assert_eq!(
+            arn_region("arn:aws:secretsmanager:eu-west-1:123456789012:secret:prod/db"),
+            Some("eu-west-1")
+        );
+        assert_eq!(
+            arn_region("arn:aws:ssm:us-east-2:123456789012:parameter/app/key"),
+            Some("us-east-2")
+        );
+        assert_eq!(arn_region("arn:aws:iam::123456789012:role/reader"), None);
+        assert_eq!(arn_region("prod/db:with:colons"), None);
+    }
+}
@@ -0,0 +1,308 @@
+use std::sync::Arc;
+
+use reqwest::StatusCode;
+use tokio::sync::OnceCell;
+
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+use super::http_error;
+
+///
+/// Key Vault REST API version used for every request.
+///
+const API_VERSION: &str = "7.4";
+
+///
+/// Instance Metadata Service endpoint that issues managed identity tokens on
+/// Azure VMs and AKS nodes.
+///
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+
+///
+/// Azure settings from the `--azure-*` options and their `AZURE_*`
+/// environment variables.
+///
+#[derive(Debug, Clone, Default)]
+pub struct AzureSettings {
+    pub tenant_id: Option<String>,
+
+    ///
+    /// The application to authenticate as with a client secret, or the
+    /// user-assigned managed identity to use without one.
+    ///
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub authority_host: String,
+
+    ///
+    /// DNS suffix of Key Vault hosts, which differs in sovereign clouds.
+    ///
+    pub vault_suffix: String,
+}
+
+///
+/// Shared Azure state for the Azure-backed providers.
+///
+/// A token is requested on first use, with the client secret if one is
+/// configured and from the managed identity otherwise, so no Azure
+/// credentials are needed unless an Azure reference is present.
+///
+pub struct Azure {
+    settings: AzureSettings,
+    client: reqwest::Client,
+    token: OnceCell<String>,
+}
+
+impl Azure {
+    pub fn new(settings: AzureSettings) -> Self {
+        Self {
+            settings,
+            client: reqwest::Client::new(),
+            token: OnceCell::new(),
+        }
+    }
+
+    ///
+    /// The resource tokens are requested for, such as `https://vault.azure.net`.
+    ///
+    fn resource(&self) -> String {
+        format!("https://{}", self.settings.vault_suffix)
+    }
+
+    async fn token(&self) -> Result<&str, ProviderError> {
+        self.token
+            .get_or_try_init(|| async {
+                if self.settings.client_secret.is_some() {
+                    self.client_secret_token().await
+                } else {
+                    self.managed_identity_token().await
+                }
+            })
+            .await
+            .map(String::as_str)
+    }
+
+    async fn client_secret_token(&self) -> Result<String, ProviderError> {
+        let settings = &self.settings;
+        let (Some(tenant_id), Some(client_id), Some(client_secret)) = (
+            &settings.tenant_id,
+            &settings.client_id,
+            &settings.client_secret,
+        ) else {
+            return Err(ProviderError::AccessDenied(
+                "client secret auth needs AZURE_TENANT_ID and AZURE_CLIENT_ID".to_string(),
+            ));
+        };
+
+        tracing::debug!("Requesting an Azure token for client {}", client_id);
+
+        let scope = format!("{}/.default", self.resource());
+        let request = self
+            .client
+            .post(format!(
+                "{}/{tenant_id}/oauth2/v2.0/token",
+                settings.authority_host.trim_end_matches('/')
+            ))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+                ("scope", &scope),
+            ]);
+
+        self.request_token(request).await
+    }
+
+    ///
+    /// A token for the managed identity, from the identity endpoint App
+    /// Service and Container Apps inject into the environment, or from the
+    /// Instance Metadata Service elsewhere.
+    ///
+    async fn managed_identity_token(&self) -> Result<String, ProviderError> {
+        let resource = self.resource();
+        let mut query = vec![("resource", resource.as_str())];
+        if let Some(client_id) = &self.settings.client_id {
+            query.push(("client_id", client_id));
+        }
+
+        // Set by the platform rather than the user, so not exposed as options
+        let request = match (
+            std::env::var("IDENTITY_ENDPOINT"),
+            std::env::var("IDENTITY_HEADER"),
+        ) {
+            (Ok(endpoint), Ok(header)) => {
+                query.push(("api-version", "2019-08-01"));
+                self.client
+                    .get(endpoint)
+                    .header("X-IDENTITY-HEADER", header)
+            }
+            _ => {
+                query.push(("api-version", "2018-02-01"));
+                self.client.get(IMDS_TOKEN_URL).header("Metadata", "true")
+            }
+        };
+
+        tracing::debug!("Requesting an Azure token for the managed identity");
+
+        self.request_token(request.query(&query)).await
+    }
+
+    async fn request_token(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<String, ProviderError> {
+        let response = request.send().await.map_err(http_error)?;
+        let status = response.status();
+        let body = response.text().await.map_err(http_error)?;
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap_or_default();
+
+        if status.is_success()
+            && let Some(token) = body.get("access_token").and_then(|token| token.as_str())
+        {
+            return Ok(token.to_string());
+        }
+
+        // Token endpoints report `{"error": "...", "error_description": "..."}`
+        let message = format!(
+            "failed to get an Azure token: HTTP {}: {}",
+            status.as_u16(),
+            body.get("error_description")
+                .or(body.get("error"))
+                .and_then(|message| message.as_str())
+                .unwrap_or("no access token")
+        );
+
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            Err(ProviderError::Transport(message))
+        } else {
+            Err(ProviderError::AccessDenied(message))
+        }
+    }
+
+    ///
+    /// Fetch a secret from a Key Vault, or a specific version of it.
+    ///
+    pub async fn get_secret(
+        &self,
+        vault: &str,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<String, ProviderError> {
+        let token = self.token().await?;
+
+        let mut url = format!(
+            "https://{vault}.{}/secrets/{name}",
+            self.settings.vault_suffix
+        );
+        if let Some(version) = version {
+            url = format!("{url}/{version}");
+        }
+
+        let response = self
+            .client
+            .get(url)
+            .query(&[("api-version", API_VERSION)])
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(http_error)?;
+        let status = response.status();
+        let body = response.text().await.map_err(http_error)?;
+
+        if !status.is_success() {
+            return Err(status_error(status, &body));
+        }
+
+        serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|body| Some(body.get("value")?.as_str()?.to_string()))
+            .ok_or_else(|| ProviderError::NotFound(format!("secret {name} has no value")))
+    }
+}
+
+///
+/// Classify an error response, keeping the code and message from the
+/// `{"error": {"code": ..., "message": ...}}` body Key Vault returns.
+///
+fn status_error(status: StatusCode, body: &str) -> ProviderError {
+    let error = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body.get("error").cloned());
+    let message = format!(
+        "{}: {}",
+        error
+            .as_ref()
+            .and_then(|error| error.get("code")?.as_str())
+            .unwrap_or("UnknownError"),
+        error
+            .as_ref()
+            .and_then(|error| error.get("message")?.as_str())
+            .unwrap_or("no message")
+    );
+
+    match status {
+        StatusCode::NOT_FOUND => ProviderError::NotFound(message),
+        StatusCode::BAD_REQUEST => ProviderError::Invalid(message),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ProviderError::AccessDenied(message),
+        StatusCode::TOO_MANY_REQUESTS => ProviderError::Throttled(message),
+        status if status.is_server_error() => ProviderError::Transport(message),
+        _ => ProviderError::Service(message),
+    }
+}
+
+///
+/// `azure_kv::<vault-name>/<secret-name>[/<version>][#field]` - loads a
+/// secret from Azure Key Vault.
+///
+/// The latest version is loaded unless a version ID is given.
+///
+pub struct KeyVault(pub Arc<Azure>);
+
+#[async_trait::async_trait]
+impl Provider for KeyVault {
+    fn scheme(&self) -> &'static str {
+        "azure_kv"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: false,
+            idempotent: true,
+        }
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        let invalid = || {
+            ProviderError::Invalid(format!(
+                "{} is not a Key Vault secret, expected <vault-name>/<secret-name>[/<version>]",
+                locator.path
+            ))
+        };
+
+        let segments = locator.path.split('/').collect::<Vec<_>>();
+        if !segments.iter().all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        }) {
+            return Err(invalid());
+        }
+
+        let (vault, name, version) = match segments.as_slice() {
+            [vault, name] => (*vault, *name, None),
+            [vault, name, version] => (*vault, *name, Some(*version)),
+            _ => return Err(invalid()),
+        };
+
+        self.0
+            .get_secret(vault, name, version)
+            .await
+            .map(Secret::Text)
+    }
+}
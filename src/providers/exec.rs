@@ -0,0 +1,139 @@
+use std::process::Stdio;
+
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+use tokio::io::AsyncWriteExt;
+
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+///
+/// `exec::<command>` - runs a command with `sh -c` and uses what it
+/// prints on stdout as the value, with trailing newlines removed as in
+/// shell command substitution.
+///
+/// Running commands taken from the environment is refused unless the loader
+/// was started with `--allow-exec`. Each run is bounded by `--call-timeout`,
+/// after which the command is killed.
+///
+pub struct Exec {
+    pub allowed: bool,
+}
+
+///
+/// Kills a command's process group when dropped, so that a command abandoned
+/// because of a timeout does not leave its children running.
+///
+struct ProcessGroup(Option<Pid>);
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            let _ = killpg(pid, Signal::SIGKILL);
+        }
+    }
+}
+
+///
+/// Run a command in its own process group with `input` on stdin, collecting
+/// its stdout and stderr.
+///
+/// If the returned future is dropped before the command exits, the whole
+/// process group is killed.
+///
+pub async fn run_command(
+    mut command: tokio::process::Command,
+    input: &[u8],
+) -> std::io::Result<std::process::Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    let mut group = ProcessGroup(child.id().map(|pid| Pid::from_raw(pid as i32)));
+
+    let mut stdin = child.stdin.take();
+    let write = async {
+        if let Some(stdin) = &mut stdin {
+            // A command that exits without reading its input is not an error
+            let _ = stdin.write_all(input).await;
+        }
+        drop(stdin);
+    };
+
+    let ((), output) = tokio::join!(write, child.wait_with_output());
+    group.0 = None;
+
+    output
+}
+
+#[async_trait::async_trait]
+impl Provider for Exec {
+    fn scheme(&self) -> &'static str {
+        "exec"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: false,
+            idempotent: true,
+        }
+    }
+
+    // `#` is common in shell commands, fields are selected with `jq` instead
+    fn field_suffix(&self) -> bool {
+        false
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        if !self.allowed {
+            return Err(ProviderError::Invalid(
+                "exec:: references are disabled, pass --allow-exec to run commands from the environment"
+                    .to_string(),
+            ));
+        }
+
+        tracing::debug!("Running {}", locator.path);
+
+        let mut command = tokio::process::Command::new("sh");
+        command.arg("-c").arg(&locator.path);
+
+        let output = run_command(command, &[]).await.map_err(|error| {
+            ProviderError::Invalid(format!("cannot run {}: {error}", locator.path))
+        })?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+
+        if !output.status.success() {
+            return Err(ProviderError::Service(format!(
+                "{} failed ({}): {}",
+                locator.path,
+                output.status,
+                if stderr.is_empty() {
+                    "no output on stderr"
+                } else {
+                    stderr
+                }
+            )));
+        }
+
+        if !stderr.is_empty() {
+            tracing::debug!("{} wrote to stderr: {}", locator.path, stderr);
+        }
+
+        let stdout = String::from_utf8(output.stdout).map_err(|_| {
+            ProviderError::Invalid(format!("{} printed output that is not UTF-8", locator.path))
+        })?;
+
+        Ok(Secret::Text(
+            stdout.trim_end_matches(['\n', '\r']).to_string(),
+        ))
+    }
+}
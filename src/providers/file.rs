@@ -0,0 +1,156 @@
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+///
+/// Largest file read when a reference does not set `max_size`.
+///
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+///
+/// What is removed from the end of a file's contents, chosen with the
+/// `trim` option.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Trim {
+    ///
+    /// The contents are used exactly as stored.
+    ///
+    #[default]
+    None,
+
+    ///
+    /// A single trailing `\n` or `\r\n`, as left by `echo` or an editor.
+    ///
+    Newline,
+
+    ///
+    /// All leading and trailing whitespace.
+    ///
+    Whitespace,
+}
+
+impl Trim {
+    fn parse(trim: &str) -> Result<Self, ProviderError> {
+        match trim {
+            "none" => Ok(Trim::None),
+            "newline" => Ok(Trim::Newline),
+            "whitespace" => Ok(Trim::Whitespace),
+            other => Err(ProviderError::Invalid(format!(
+                "unknown trim mode {other}, expected none, newline or whitespace"
+            ))),
+        }
+    }
+
+    fn apply(self, bytes: &[u8]) -> &[u8] {
+        match self {
+            Trim::None => bytes,
+            Trim::Newline => bytes
+                .strip_suffix(b"\r\n")
+                .or_else(|| bytes.strip_suffix(b"\n"))
+                .unwrap_or(bytes),
+            Trim::Whitespace => bytes.trim_ascii(),
+        }
+    }
+}
+
+///
+/// The `trim` option of a reference, or no trimming.
+///
+pub fn trim(locator: &Locator) -> Result<Trim, ProviderError> {
+    match locator.option("trim") {
+        Some(trim) => Trim::parse(trim),
+        None => Ok(Trim::default()),
+    }
+}
+
+///
+/// The `max_size` option of a reference, or the default limit.
+///
+pub fn max_size(locator: &Locator) -> Result<u64, ProviderError> {
+    match locator.option("max_size") {
+        Some(size) => size.parse().map_err(|_| {
+            ProviderError::Invalid(format!("max_size {size} is not a number of bytes"))
+        }),
+        None => Ok(DEFAULT_MAX_SIZE),
+    }
+}
+
+///
+/// Read a file of at most `max_size` bytes, trimming it as requested.
+///
+/// Text is returned as `Secret::Text` and anything that is not UTF-8 as
+/// `Secret::Binary`.
+///
+pub fn read_file(path: &Path, max_size: u64, trim: Trim) -> Result<Secret, ProviderError> {
+    let file = std::fs::File::open(path).map_err(|error| io_error(path, error))?;
+
+    // Read one byte past the limit rather than trusting the reported size,
+    // which is zero for many special files
+    let mut bytes = Vec::new();
+    file.take(max_size.saturating_add(1))
+        .read_to_end(&mut bytes)
+        .map_err(|error| io_error(path, error))?;
+
+    if bytes.len() as u64 > max_size {
+        return Err(ProviderError::Invalid(format!(
+            "{} is larger than {max_size} bytes, raise max_size to read it",
+            path.display()
+        )));
+    }
+
+    let bytes = trim.apply(&bytes).to_vec();
+
+    Ok(match String::from_utf8(bytes) {
+        Ok(text) => Secret::Text(text),
+        Err(error) => Secret::Binary(error.into_bytes()),
+    })
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> ProviderError {
+    let message = format!("{}: {error}", path.display());
+
+    match error.kind() {
+        ErrorKind::NotFound => ProviderError::NotFound(message),
+        ErrorKind::PermissionDenied => ProviderError::AccessDenied(message),
+        _ => ProviderError::Invalid(message),
+    }
+}
+
+///
+/// `file::<path>[?trim=<mode>&max_size=<bytes>][#field]` - reads the value
+/// from a file, such as a Docker or Kubernetes secret mounted under
+/// `/run/secrets`.
+///
+/// Relative paths are resolved against the working directory. Files that
+/// are not UTF-8 text are exposed according to the `binary` option.
+///
+pub struct File;
+
+#[async_trait::async_trait]
+impl Provider for File {
+    fn scheme(&self) -> &'static str {
+        "file"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: true,
+            idempotent: true,
+        }
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        &["trim", "max_size"]
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        read_file(Path::new(&locator.path), max_size(locator)?, trim(locator)?)
+    }
+}
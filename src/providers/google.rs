@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use base64::Engine;
+use reqwest::StatusCode;
+use tokio::sync::OnceCell;
+
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+use super::http_error;
+
+///
+/// OAuth scope requested for Secret Manager calls.
+///
+const SCOPES: &[&str] = &["https://www.googleapis.com/auth/cloud-platform"];
+
+///
+/// Shared Google Cloud state for the Google-backed providers.
+///
+/// Credentials are discovered lazily on first use through Application
+/// Default Credentials (`GOOGLE_APPLICATION_CREDENTIALS`, the gcloud
+/// configuration, or the metadata server, which provides workload identity
+/// on GKE), so none are needed unless a Google reference is present.
+///
+pub struct Google {
+    endpoint: String,
+    client: reqwest::Client,
+    auth: OnceCell<Arc<dyn gcp_auth::TokenProvider>>,
+}
+
+impl Google {
+    pub fn new(endpoint: String) -> Self {
+        Self {
+            endpoint,
+            client: reqwest::Client::new(),
+            auth: OnceCell::new(),
+        }
+    }
+
+    async fn token(&self) -> Result<Arc<gcp_auth::Token>, ProviderError> {
+        let auth = self
+            .auth
+            .get_or_try_init(gcp_auth::provider)
+            .await
+            .map_err(auth_error)?;
+
+        auth.token(SCOPES).await.map_err(auth_error)
+    }
+
+    ///
+    /// Fetch the payload of a secret version with `AccessSecretVersion`.
+    ///
+    pub async fn access_secret_version(&self, name: &str) -> Result<Vec<u8>, ProviderError> {
+        let token = self.token().await?;
+
+        let response = self
+            .client
+            .get(format!(
+                "{}/v1/{name}:access",
+                self.endpoint.trim_end_matches('/')
+            ))
+            .bearer_auth(token.as_str())
+            .send()
+            .await
+            .map_err(http_error)?;
+        let status = response.status();
+        let body = response.text().await.map_err(http_error)?;
+
+        if !status.is_success() {
+            return Err(status_error(status, &body));
+        }
+
+        let payload = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|body| Some(body.pointer("/payload/data")?.as_str()?.to_string()))
+            .ok_or_else(|| ProviderError::Service(format!("{name} returned no payload")))?;
+
+        base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .map_err(|error| {
+                ProviderError::Service(format!("{name} payload is not base64: {error}"))
+            })
+    }
+}
+
+///
+/// Classify a failure to find or use credentials.
+///
+fn auth_error(error: gcp_auth::Error) -> ProviderError {
+    match error {
+        // The metadata server or token endpoint could not be reached
+        gcp_auth::Error::Http(..) => ProviderError::Transport(error.to_string()),
+        gcp_auth::Error::NoAuthMethod(gcloud, metadata, config) => {
+            ProviderError::AccessDenied(format!(
+                "no Google credentials found (application default credentials: {config}; \
+                 metadata server: {metadata}; gcloud: {gcloud})"
+            ))
+        }
+        error => ProviderError::AccessDenied(error.to_string()),
+    }
+}
+
+///
+/// Classify an error response, keeping the status and message from the
+/// `{"error": {"status": ..., "message": ...}}` body Google APIs return.
+///
+fn status_error(status: StatusCode, body: &str) -> ProviderError {
+    let error = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body.get("error").cloned());
+    let code = error
+        .as_ref()
+        .and_then(|error| error.get("status")?.as_str())
+        .unwrap_or("UNKNOWN");
+    let message = format!(
+        "{code}: {}",
+        error
+            .as_ref()
+            .and_then(|error| error.get("message")?.as_str())
+            .unwrap_or("no message")
+    );
+
+    match code {
+        "NOT_FOUND" => ProviderError::NotFound(message),
+        "INVALID_ARGUMENT" => ProviderError::Invalid(message),
+        "PERMISSION_DENIED" | "UNAUTHENTICATED" => ProviderError::AccessDenied(message),
+        "RESOURCE_EXHAUSTED" => ProviderError::Throttled(message),
+        "UNAVAILABLE" | "INTERNAL" | "DEADLINE_EXCEEDED" => ProviderError::Transport(message),
+        _ if status.is_server_error() => ProviderError::Transport(message),
+        _ => ProviderError::Service(message),
+    }
+}
+
+///
+/// The full resource name of the secret version a path refers to, with
+/// `latest` filled in when the path names only the secret.
+///
+fn version_name(path: &str) -> Result<String, ProviderError> {
+    let segments = path.split('/').collect::<Vec<_>>();
+
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return Err(invalid_name(path));
+    }
+
+    match segments.as_slice() {
+        ["projects", _, "secrets", _] => Ok(format!("{path}/versions/latest")),
+        ["projects", _, "secrets", _, "versions", _] => Ok(path.to_string()),
+        _ => Err(invalid_name(path)),
+    }
+}
+
+fn invalid_name(path: &str) -> ProviderError {
+    ProviderError::Invalid(format!(
+        "{path} is not a secret version, expected projects/<project>/secrets/<secret>/versions/<version>"
+    ))
+}
+
+///
+/// `gcp_sm::projects/<project>/secrets/<secret>[/versions/<version>][#field]` -
+/// loads a secret version from Google Cloud Secret Manager.
+///
+/// The version may be a number or an alias such as `latest`, which is also
+/// used when no version is given. Payloads that are not UTF-8 text are
+/// exposed according to the `binary` option.
+///
+pub struct GoogleSecretManager(pub Arc<Google>);
+
+#[async_trait::async_trait]
+impl Provider for GoogleSecretManager {
+    fn scheme(&self) -> &'static str {
+        "gcp_sm"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: true,
+            idempotent: true,
+        }
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        let name = version_name(&locator.path)?;
+        let payload = self.0.access_secret_version(&name).await?;
+
+        Ok(match String::from_utf8(payload) {
+            Ok(text) => Secret::Text(text),
+            Err(error) => Secret::Binary(error.into_bytes()),
+        })
+    }
+}
@@ -0,0 +1,86 @@
+mod amazon;
+mod azure;
+mod exec;
+mod file;
+mod google;
+mod plugin;
+mod systemd;
+mod value;
+mod vault;
+
+pub use amazon::{Amazon, AssumeRole, ParameterStore, SecretsManager};
+pub use azure::{Azure, AzureSettings, KeyVault};
+pub use exec::Exec;
+pub use file::File;
+pub use google::{Google, GoogleSecretManager};
+pub use plugin::{PLUGIN_PREFIX, Plugin};
+pub use systemd::SystemdCredential;
+pub use value::Value;
+pub use vault::{DynamicSecret, KeyValue, Vault, VaultAuth, VaultSettings};
+
+use std::sync::Arc;
+
+use crate::Application;
+use crate::provider::{ProviderError, Registry};
+
+///
+/// Build the registry of every provider compiled into the loader,
+/// configured from the command line.
+///
+pub fn registry(application: &Application) -> Registry {
+    let mut registry = Registry::new();
+
+    registry.register(Value);
+    registry.register(File);
+    registry.register(SystemdCredential);
+    registry.register(Exec {
+        allowed: application.allow_exec,
+    });
+
+    let amazon = Arc::new(Amazon::new(AssumeRole {
+        role_arn: application.assume_role.clone(),
+        external_id: application.external_id.clone(),
+        session_name: application.role_session_name.clone(),
+    }));
+    registry.register(SecretsManager(amazon.clone()));
+    registry.register(ParameterStore(amazon));
+
+    let google = Arc::new(Google::new(application.gcp_endpoint.clone()));
+    registry.register(GoogleSecretManager(google));
+
+    let azure = Arc::new(Azure::new(AzureSettings {
+        tenant_id: application.azure_tenant_id.clone(),
+        client_id: application.azure_client_id.clone(),
+        client_secret: application.azure_client_secret.clone(),
+        authority_host: application.azure_authority_host.clone(),
+        vault_suffix: application.azure_vault_suffix.clone(),
+    }));
+    registry.register(KeyVault(azure));
+
+    let vault = Arc::new(Vault::new(VaultSettings {
+        address: application.vault_addr.clone(),
+        namespace: application.vault_namespace.clone(),
+        auth: application.vault_auth,
+        auth_mount: application.vault_auth_mount.clone(),
+        token: application.vault_token.clone(),
+        role_id: application.vault_role_id.clone(),
+        secret_id: application.vault_secret_id.clone(),
+        role: application.vault_role.clone(),
+        kubernetes_token_path: application.vault_kubernetes_token_path.clone(),
+    }));
+    registry.register(KeyValue(vault.clone()));
+    registry.register(DynamicSecret(vault));
+
+    registry
+}
+
+///
+/// Classify a failure to send an HTTP request or read its response.
+///
+fn http_error(error: reqwest::Error) -> ProviderError {
+    if error.is_builder() {
+        ProviderError::Invalid(error.to_string())
+    } else {
+        ProviderError::Transport(error.to_string())
+    }
+}
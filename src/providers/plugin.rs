@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use base64::Engine;
+
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+use super::exec::run_command;
+
+///
+/// Executables named `<PLUGIN_PREFIX><scheme>` on `PATH` provide schemes
+/// that are not built in.
+///
+pub const PLUGIN_PREFIX: &str = "env-loader-provider-";
+
+///
+/// Version of the JSON protocol spoken with plugins, sent in every request.
+///
+const PROTOCOL_VERSION: u64 = 1;
+
+///
+/// Maximum number of references sent to a plugin in one request.
+///
+const PLUGIN_BATCH_LIMIT: usize = 100;
+
+///
+/// `<scheme>::<path>[#field]` for a scheme provided by an external
+/// `env-loader-provider-<scheme>` executable.
+///
+/// The plugin is run once per batch of references, receives them as a JSON
+/// request on stdin and answers with one value or typed error per
+/// reference on stdout. See the README for the protocol.
+///
+pub struct Plugin {
+    scheme: &'static str,
+    pub program: PathBuf,
+}
+
+impl Plugin {
+    ///
+    /// Find the plugin for `scheme` on `PATH`, if there is one.
+    ///
+    pub fn discover(scheme: &str) -> Option<Self> {
+        // Keeps the scheme from naming a path outside the PATH directories
+        let valid = !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return None;
+        }
+
+        let name = format!("{PLUGIN_PREFIX}{scheme}");
+        let program = std::env::split_paths(&std::env::var_os("PATH")?)
+            .map(|directory| directory.join(&name))
+            .find(|path| {
+                path.metadata().is_ok_and(|metadata| {
+                    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+                })
+            })?;
+
+        Some(Self {
+            // Providers are registered for the life of the process under a
+            // static scheme, and only one plugin is discovered per scheme
+            scheme: Box::leak(scheme.to_string().into_boxed_str()),
+            program,
+        })
+    }
+
+    fn failed(&self, message: String, count: usize) -> Vec<Result<Secret, ProviderError>> {
+        let error = ProviderError::Service(format!("plugin {}: {message}", self.program.display()));
+        vec![Err(error); count]
+    }
+}
+
+///
+/// Convert one entry of a plugin's `results` into a value or error.
+///
+fn plugin_result(result: &serde_json::Value) -> Result<Secret, ProviderError> {
+    if let Some(value) = result.get("value").and_then(serde_json::Value::as_str) {
+        return Ok(Secret::Text(value.to_string()));
+    }
+
+    if let Some(value) = result
+        .get("value_base64")
+        .and_then(serde_json::Value::as_str)
+    {
+        return base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .map(Secret::Binary)
+            .map_err(|error| {
+                ProviderError::Service(format!("value_base64 is not base64: {error}"))
+            });
+    }
+
+    let Some(error) = result.get("error") else {
+        return Err(ProviderError::Service(
+            "result has no value, value_base64 or error".to_string(),
+        ));
+    };
+
+    let message = error
+        .get("message")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or("no message")
+        .to_string();
+
+    match error.get("kind").and_then(serde_json::Value::as_str) {
+        Some("not_found") => Err(ProviderError::NotFound(message)),
+        Some("invalid") => Err(ProviderError::Invalid(message)),
+        Some("access_denied") => Err(ProviderError::AccessDenied(message)),
+        Some("decryption") => Err(ProviderError::Decryption(message)),
+        Some("throttled") => Err(ProviderError::Throttled(message)),
+        Some("transport") => Err(ProviderError::Transport(message)),
+        _ => Err(ProviderError::Service(message)),
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for Plugin {
+    fn scheme(&self) -> &'static str {
+        self.scheme
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: Some(PLUGIN_BATCH_LIMIT),
+            binary: false,
+            idempotent: true,
+        }
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        self.resolve_batch(&[locator]).await.remove(0)
+    }
+
+    async fn resolve_batch(&self, locators: &[&Locator]) -> Vec<Result<Secret, ProviderError>> {
+        let request = serde_json::json!({
+            "version": PROTOCOL_VERSION,
+            "scheme": self.scheme,
+            "references": locators
+                .iter()
+                .enumerate()
+                .map(|(id, locator)| serde_json::json!({ "id": id, "path": locator.path }))
+                .collect::<Vec<_>>(),
+        });
+
+        tracing::debug!(
+            "Running plugin {} for {} references",
+            self.program.display(),
+            locators.len()
+        );
+
+        let output = match run_command(
+            tokio::process::Command::new(&self.program),
+            request.to_string().as_bytes(),
+        )
+        .await
+        {
+            Ok(output) => output,
+            Err(error) => return self.failed(format!("cannot run: {error}"), locators.len()),
+        };
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+
+        if !output.status.success() {
+            let stderr = if stderr.is_empty() {
+                "no output on stderr"
+            } else {
+                stderr
+            };
+            return self.failed(format!("{}: {stderr}", output.status), locators.len());
+        }
+
+        if !stderr.is_empty() {
+            tracing::debug!(
+                "Plugin {} wrote to stderr: {}",
+                self.program.display(),
+                stderr
+            );
+        }
+
+        let response = match serde_json::from_slice::<serde_json::Value>(&output.stdout) {
+            Ok(response) => response,
+            Err(error) => return self.failed(format!("invalid response: {error}"), locators.len()),
+        };
+
+        let results = response
+            .get("results")
+            .and_then(serde_json::Value::as_array)
+            .map(|results| {
+                results
+                    .iter()
+                    .filter_map(|result| Some((result.get("id")?.as_u64()?, result)))
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+
+        (0..locators.len() as u64)
+            .map(|id| match results.get(&id) {
+                Some(result) => plugin_result(result),
+                None => Err(ProviderError::Service(format!(
+                    "plugin {} returned no result for {}",
+                    self.program.display(),
+                    locators[id as usize].path
+                ))),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_values() {
+        assert_eq!(
+            plugin_result(&json!({"value": "hunter2"})).unwrap(),
+            Secret::Text("hunter2".to_string())
+        );
+        assert_eq!(
+            plugin_result(&json!({"value_base64": "AAEC"})).unwrap(),
+            Secret::Binary(vec![0, 1, 2])
+        );
+        assert!(matches!(
+            plugin_result(&json!({"value_base64": "not base64!"})),
+            Err(ProviderError::Service(_))
+        ));
+        assert!(matches!(
+            plugin_result(&json!({"value": 1})),
+            Err(ProviderError::Service(_))
+        ));
+    }
+
+    #[test]
+    fn classifies_errors() {
+        let error = |kind| plugin_result(&json!({"error": {"kind": kind, "message": "boom"}}));
+
+        assert!(
+            matches!(error("not_found"), Err(ProviderError::NotFound(message)) if message == "boom")
+        );
+        assert!(matches!(error("invalid"), Err(ProviderError::Invalid(_))));
+        assert!(matches!(
+            error("access_denied"),
+            Err(ProviderError::AccessDenied(_))
+        ));
+        assert!(matches!(
+            error("decryption"),
+            Err(ProviderError::Decryption(_))
+        ));
+        assert!(matches!(
+            error("throttled"),
+            Err(ProviderError::Throttled(_))
+        ));
+        assert!(matches!(
+            error("transport"),
+            Err(ProviderError::Transport(_))
+        ));
+        assert!(matches!(error("other"), Err(ProviderError::Service(_))));
+        assert!(matches!(
+            plugin_result(&json!({"error": {}})),
+            Err(ProviderError::Service(message)) if message == "no message"
+        ));
+    }
+}
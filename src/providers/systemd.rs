@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+use super::file::{max_size, read_file, trim};
+
+///
+/// `systemd_cred::<name>[?trim=<mode>&max_size=<bytes>][#field]` - reads a
+/// credential passed to the service with `LoadCredential=`,
+/// `LoadCredentialEncrypted=` or `SetCredential=`.
+///
+/// Credentials are files in the directory systemd names in
+/// `$CREDENTIALS_DIRECTORY`, already decrypted, and take the same options
+/// as `file::` references.
+///
+pub struct SystemdCredential;
+
+#[async_trait::async_trait]
+impl Provider for SystemdCredential {
+    fn scheme(&self) -> &'static str {
+        "systemd_cred"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: true,
+            idempotent: true,
+        }
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        &["trim", "max_size"]
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        let name = &locator.path;
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(ProviderError::Invalid(format!(
+                "{name} is not a credential name, names cannot be empty or contain /"
+            )));
+        }
+
+        let Some(directory) = std::env::var_os("CREDENTIALS_DIRECTORY") else {
+            return Err(ProviderError::Invalid(format!(
+                "cannot load credential {name}: CREDENTIALS_DIRECTORY is not set, start the \
+                 service with LoadCredential= or LoadCredentialEncrypted= for it"
+            )));
+        };
+
+        read_file(
+            &PathBuf::from(directory).join(name),
+            max_size(locator)?,
+            trim(locator)?,
+        )
+    }
+}
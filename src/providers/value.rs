@@ -0,0 +1,29 @@
+use crate::provider::{Capabilities, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+///
+/// `value::<literal>` - passes the remainder through as the value directly.
+///
+pub struct Value;
+
+#[async_trait::async_trait]
+impl Provider for Value {
+    fn scheme(&self) -> &'static str {
+        "value"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: false,
+            json_fields: false,
+            batch: None,
+            binary: false,
+            idempotent: true,
+        }
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        Ok(Secret::Text(locator.path.clone()))
+    }
+}
@@ -0,0 +1,956 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tokio::sync::OnceCell;
+
+use crate::provider::{Capabilities, Lease, Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+use super::http_error;
+
+///
+/// ID under which the client token is renewed alongside the leases on
+/// dynamic secrets.
+///
+const TOKEN_LEASE_ID: &str = "auth/token/self";
+
+///
+/// How the loader authenticates to Vault, from `--vault-auth`.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum VaultAuth {
+    ///
+    /// Use an existing token from `--vault-token` or `~/.vault-token`.
+    ///
+    #[default]
+    Token,
+
+    ///
+    /// Log in with an AppRole role ID and secret ID.
+    ///
+    Approle,
+
+    ///
+    /// Log in with the pod's Kubernetes service account token.
+    ///
+    Kubernetes,
+}
+
+impl VaultAuth {
+    ///
+    /// The path the auth method is mounted at when `--vault-auth-mount` is not given.
+    ///
+    fn default_mount(self) -> &'static str {
+        match self {
+            VaultAuth::Token => "token",
+            VaultAuth::Approle => "approle",
+            VaultAuth::Kubernetes => "kubernetes",
+        }
+    }
+}
+
+///
+/// Connection and authentication settings for Vault, from the `--vault-*`
+/// options and their `VAULT_*` environment variables.
+///
+#[derive(Debug, Clone, Default)]
+pub struct VaultSettings {
+    pub address: Option<String>,
+    pub namespace: Option<String>,
+    pub auth: VaultAuth,
+    pub auth_mount: Option<String>,
+    pub token: Option<String>,
+    pub role_id: Option<String>,
+    pub secret_id: Option<String>,
+
+    ///
+    /// The Vault role to log in as with Kubernetes auth.
+    ///
+    pub role: Option<String>,
+    pub kubernetes_token_path: PathBuf,
+}
+
+///
+/// Shared Vault state for the Vault-backed providers.
+///
+/// The loader logs in on first use, so no Vault configuration is needed
+/// unless a Vault reference is present, and the token is reused for every
+/// later request. Leases on dynamic secrets are recorded as they are read.
+///
+pub struct Vault {
+    settings: VaultSettings,
+    client: reqwest::Client,
+    token: OnceCell<String>,
+
+    ///
+    /// The client token's own lease, or `None` if it does not expire.
+    ///
+    token_lease: OnceCell<Option<Lease>>,
+    leases: Mutex<Vec<Lease>>,
+}
+
+impl Vault {
+    pub fn new(settings: VaultSettings) -> Self {
+        Self {
+            settings,
+            client: reqwest::Client::new(),
+            token: OnceCell::new(),
+            token_lease: OnceCell::new(),
+            leases: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn url(&self, path: &str) -> Result<String, ProviderError> {
+        let Some(address) = &self.settings.address else {
+            return Err(ProviderError::Invalid(
+                "no Vault address, set --vault-addr or VAULT_ADDR".to_string(),
+            ));
+        };
+
+        Ok(format!(
+            "{}/v1/{}",
+            address.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        ))
+    }
+
+    ///
+    /// The client token, logging in with the configured auth method the
+    /// first time it is needed.
+    ///
+    async fn token(&self) -> Result<&str, ProviderError> {
+        self.token
+            .get_or_try_init(|| self.login())
+            .await
+            .map(String::as_str)
+    }
+
+    async fn login(&self) -> Result<String, ProviderError> {
+        let settings = &self.settings;
+        let mount = settings
+            .auth_mount
+            .as_deref()
+            .unwrap_or(settings.auth.default_mount());
+
+        let body = match settings.auth {
+            VaultAuth::Token => return self.existing_token(),
+            VaultAuth::Approle => {
+                let Some(role_id) = &settings.role_id else {
+                    return Err(ProviderError::AccessDenied(
+                        "AppRole auth needs --vault-role-id or VAULT_ROLE_ID".to_string(),
+                    ));
+                };
+                serde_json::json!({ "role_id": role_id, "secret_id": settings.secret_id })
+            }
+            VaultAuth::Kubernetes => {
+                let Some(role) = &settings.role else {
+                    return Err(ProviderError::AccessDenied(
+                        "Kubernetes auth needs --vault-role".to_string(),
+                    ));
+                };
+                let jwt =
+                    std::fs::read_to_string(&settings.kubernetes_token_path).map_err(|error| {
+                        ProviderError::AccessDenied(format!(
+                            "cannot read service account token {}: {error}",
+                            settings.kubernetes_token_path.display()
+                        ))
+                    })?;
+                serde_json::json!({ "role": role, "jwt": jwt.trim() })
+            }
+        };
+
+        tracing::debug!("Logging in to Vault with auth/{}", mount);
+
+        let request = self
+            .client
+            .post(self.url(&format!("auth/{mount}/login"))?)
+            .json(&body);
+
+        // Vault answers a rejected login with 400 rather than 403
+        let response = self.send(request).await.map_err(|error| match error {
+            ProviderError::Invalid(message) => {
+                ProviderError::AccessDenied(format!("login to auth/{mount} failed: {message}"))
+            }
+            error => error,
+        })?;
+
+        let _ = self.token_lease.set(auth_lease(&response));
+
+        response
+            .pointer("/auth/client_token")
+            .and_then(serde_json::Value::as_str)
+            .map(String::from)
+            .ok_or_else(|| {
+                ProviderError::Service(format!("login to auth/{mount} returned no client token"))
+            })
+    }
+
+    ///
+    /// The client token's lease, from the login response or, for an existing
+    /// token, looked up the first time it is needed.
+    ///
+    async fn token_lease(&self) -> Option<Lease> {
+        self.token_lease
+            .get_or_init(|| async {
+                match self.read("auth/token/lookup-self", &[]).await {
+                    Ok(response) => {
+                        let ttl = response.pointer("/data/ttl")?.as_u64()?;
+                        (ttl > 0).then(|| Lease {
+                            id: TOKEN_LEASE_ID.to_string(),
+                            duration: Duration::from_secs(ttl),
+                            renewable: response
+                                .pointer("/data/renewable")
+                                .and_then(serde_json::Value::as_bool)
+                                .unwrap_or(false),
+                        })
+                    }
+                    Err(error) => {
+                        tracing::warn!(
+                            "Cannot look up the Vault token, it will not be renewed: {}",
+                            error
+                        );
+                        None
+                    }
+                }
+            })
+            .await
+            .clone()
+    }
+
+    ///
+    /// The token given with `--vault-token`, or the one the Vault CLI stores
+    /// in `~/.vault-token` after `vault login`.
+    ///
+    fn existing_token(&self) -> Result<String, ProviderError> {
+        if let Some(token) = self
+            .settings
+            .token
+            .as_ref()
+            .filter(|token| !token.is_empty())
+        {
+            return Ok(token.clone());
+        }
+
+        std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".vault-token"))
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| {
+                ProviderError::AccessDenied(
+                    "no Vault token, set --vault-token or VAULT_TOKEN".to_string(),
+                )
+            })
+    }
+
+    ///
+    /// `GET /v1/<path>` with the client token, returning the response body.
+    ///
+    pub async fn read(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<serde_json::Value, ProviderError> {
+        let token = self.token().await?;
+        let request = self
+            .client
+            .get(self.url(path)?)
+            .query(query)
+            .header("X-Vault-Token", token);
+
+        self.send(request).await
+    }
+
+    ///
+    /// `POST /v1/<path>` with the client token and a JSON body, returning the
+    /// response body (`null` for `204 No Content`).
+    ///
+    pub async fn write(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> Result<serde_json::Value, ProviderError> {
+        let token = self.token().await?;
+        let request = self
+            .client
+            .post(self.url(path)?)
+            .json(body)
+            .header("X-Vault-Token", token);
+
+        self.send(request).await
+    }
+
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<serde_json::Value, ProviderError> {
+        let request = match &self.settings.namespace {
+            Some(namespace) => request.header("X-Vault-Namespace", namespace),
+            None => request,
+        };
+
+        let response = request.send().await.map_err(http_error)?;
+        let status = response.status();
+        let body = response.text().await.map_err(http_error)?;
+
+        if !status.is_success() {
+            return Err(status_error(status, &body));
+        }
+
+        if body.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+
+        serde_json::from_str(&body).map_err(|error| {
+            ProviderError::Service(format!("invalid response from Vault: {error}"))
+        })
+    }
+}
+
+///
+/// The lease described by the `lease_id`, `lease_duration` and `renewable`
+/// fields of a response, if it has one.
+///
+fn response_lease(response: &serde_json::Value) -> Option<Lease> {
+    let id = response.get("lease_id")?.as_str()?;
+    if id.is_empty() {
+        return None;
+    }
+
+    Some(Lease {
+        id: id.to_string(),
+        duration: Duration::from_secs(response.get("lease_duration")?.as_u64()?),
+        renewable: response
+            .get("renewable")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false),
+    })
+}
+
+///
+/// The client token's lease from the `auth` section of a login or renewal
+/// response, if the token expires.
+///
+fn auth_lease(response: &serde_json::Value) -> Option<Lease> {
+    let duration = response.pointer("/auth/lease_duration")?.as_u64()?;
+    if duration == 0 {
+        return None;
+    }
+
+    Some(Lease {
+        id: TOKEN_LEASE_ID.to_string(),
+        duration: Duration::from_secs(duration),
+        renewable: response
+            .pointer("/auth/renewable")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false),
+    })
+}
+
+///
+/// Classify an error response, keeping the messages from Vault's
+/// `{"errors": [...]}` body.
+///
+fn status_error(status: StatusCode, body: &str) -> ProviderError {
+    let errors = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| {
+            body.get("errors")?
+                .as_array()?
+                .iter()
+                .map(|error| error.as_str().map(String::from))
+                .collect::<Option<Vec<_>>>()
+        })
+        .filter(|errors| !errors.is_empty())
+        .map(|errors| errors.join("; "))
+        .unwrap_or_else(|| "no message".to_string());
+
+    let message = format!("HTTP {}: {errors}", status.as_u16());
+
+    match status {
+        StatusCode::NOT_FOUND => ProviderError::NotFound(message),
+        StatusCode::BAD_REQUEST => ProviderError::Invalid(message),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ProviderError::AccessDenied(message),
+        StatusCode::TOO_MANY_REQUESTS => ProviderError::Throttled(message),
+        // Includes 503 from a sealed or standby node
+        status if status.is_server_error() => ProviderError::Transport(message),
+        _ => ProviderError::Service(message),
+    }
+}
+
+///
+/// `vault::<mount>/data/<path>[?version=<n>][#field]` - loads a secret from
+/// a Vault KV version 2 secrets engine.
+///
+/// The value is the secret's data as a JSON object, so a single key is
+/// usually selected with `#field`. The `version` option reads an older
+/// version instead of the latest.
+///
+pub struct KeyValue(pub Arc<Vault>);
+
+#[async_trait::async_trait]
+impl Provider for KeyValue {
+    fn scheme(&self) -> &'static str {
+        "vault"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: false,
+            idempotent: true,
+        }
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        &["version"]
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        let query = match locator.option("version") {
+            Some(version) if version.parse::<u64>().is_err() => {
+                return Err(ProviderError::Invalid(format!(
+                    "version {version} of {} is not a version number",
+                    locator.path
+                )));
+            }
+            Some(version) => vec![("version", version)],
+            None => Vec::new(),
+        };
+
+        let response = self.0.read(&locator.path, &query).await?;
+
+        match response.pointer("/data/data") {
+            Some(data @ serde_json::Value::Object(_)) => Ok(Secret::Text(data.to_string())),
+            // A deleted (but not destroyed) version is returned with null data
+            Some(serde_json::Value::Null) => Err(ProviderError::NotFound(format!(
+                "{} has been deleted",
+                locator.path
+            ))),
+            _ => Err(ProviderError::Invalid(format!(
+                "{} is not a KV version 2 secret, expected a <mount>/data/<path> path",
+                locator.path
+            ))),
+        }
+    }
+}
+
+///
+/// `vault_dynamic::<path>[#field]` - reads credentials from a Vault dynamic
+/// secrets engine, such as `database/creds/<role>`.
+///
+/// Every read issues new credentials, so references that select different
+/// fields of the same path share one read. The credentials' lease is
+/// recorded so that `--supervise` can renew it while the command runs and
+/// revoke it afterwards. Vault revokes every lease created with a token when
+/// the token expires, so the client token is renewed along with them.
+///
+pub struct DynamicSecret(pub Arc<Vault>);
+
+#[async_trait::async_trait]
+impl Provider for DynamicSecret {
+    fn scheme(&self) -> &'static str {
+        "vault_dynamic"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sensitive: true,
+            json_fields: true,
+            batch: None,
+            binary: false,
+            idempotent: false,
+        }
+    }
+
+    async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+        let response = self.0.read(&locator.path, &[]).await?;
+
+        let Some(data @ serde_json::Value::Object(_)) = response.get("data") else {
+            return Err(ProviderError::Invalid(format!(
+                "{} returned no data",
+                locator.path
+            )));
+        };
+
+        if let Some(lease) = response_lease(&response) {
+            tracing::debug!(
+                "Read {} with lease {} for {:?}",
+                locator.path,
+                lease.id,
+                lease.duration
+            );
+            self.0.leases.lock().unwrap().push(lease);
+            self.0.token_lease().await;
+        }
+
+        Ok(Secret::Text(data.to_string()))
+    }
+
+    fn leases(&self) -> Vec<Lease> {
+        let mut leases = self.0.leases.lock().unwrap().clone();
+
+        if !leases.is_empty()
+            && let Some(Some(token)) = self.0.token_lease.get()
+        {
+            leases.push(token.clone());
+        }
+
+        leases
+    }
+
+    async fn renew_lease(&self, lease: &Lease) -> Result<Lease, ProviderError> {
+        if lease.id == TOKEN_LEASE_ID {
+            let response = self
+                .0
+                .write("auth/token/renew-self", &serde_json::json!({}))
+                .await?;
+
+            return auth_lease(&response).ok_or_else(|| {
+                ProviderError::Service("renewing the token returned no lease".to_string())
+            });
+        }
+
+        let response = self
+            .0
+            .write(
+                "sys/leases/renew",
+                &serde_json::json!({ "lease_id": lease.id }),
+            )
+            .await?;
+
+        response_lease(&response).ok_or_else(|| {
+            ProviderError::Service(format!("renewing lease {} returned no lease", lease.id))
+        })
+    }
+
+    async fn revoke_lease(&self, lease: &Lease) -> Result<(), ProviderError> {
+        // The token may be the user's own, and the leases are revoked with it
+        // while it is still valid
+        if lease.id == TOKEN_LEASE_ID {
+            return Ok(());
+        }
+
+        self.0
+            .write(
+                "sys/leases/revoke",
+                &serde_json::json!({ "lease_id": lease.id }),
+            )
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use serde_json::json;
+
+    use super::*;
+
+    ///
+    /// A request received by the stand-in server.
+    ///
+    #[derive(Debug)]
+    struct Received {
+        request: String,
+        token: Option<String>,
+        namespace: Option<String>,
+        body: serde_json::Value,
+    }
+
+    ///
+    /// A stand-in for the Vault HTTP API answering `"<METHOD> <target>"`
+    /// requests from `routes`, and everything else with `404`.
+    ///
+    struct StandIn {
+        address: String,
+        received: Arc<Mutex<Vec<Received>>>,
+    }
+
+    impl StandIn {
+        fn start(routes: Vec<(&'static str, u16, serde_json::Value)>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = format!("http://{}", listener.local_addr().unwrap());
+            let received = Arc::new(Mutex::new(Vec::new()));
+
+            let log = received.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    // `GET /v1/path?query HTTP/1.1` without the version
+                    let request = line.rsplit_once(' ').unwrap().0.to_string();
+
+                    let mut headers = HashMap::new();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(':') else {
+                            break;
+                        };
+                        headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+                    }
+
+                    let length = headers
+                        .get("content-length")
+                        .map_or(0, |length| length.parse().unwrap());
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+
+                    let (status, response) = routes
+                        .iter()
+                        .find(|(route, _, _)| *route == request)
+                        .map_or((404, json!({"errors": []})), |(_, status, response)| {
+                            (*status, response.clone())
+                        });
+
+                    log.lock().unwrap().push(Received {
+                        request,
+                        token: headers.remove("x-vault-token"),
+                        namespace: headers.remove("x-vault-namespace"),
+                        body: serde_json::from_slice(&body).unwrap_or_default(),
+                    });
+
+                    let response = match status {
+                        204 => String::new(),
+                        _ => response.to_string(),
+                    };
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status} Stand-In\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                        response.len()
+                    );
+                }
+            });
+
+            Self { address, received }
+        }
+
+        fn vault(&self, settings: VaultSettings) -> Arc<Vault> {
+            Arc::new(Vault::new(VaultSettings {
+                address: Some(self.address.clone()),
+                ..settings
+            }))
+        }
+
+        fn received(&self) -> Vec<Received> {
+            std::mem::take(&mut self.received.lock().unwrap())
+        }
+    }
+
+    fn locator(path: &str, options: &[(&str, &str)]) -> Locator {
+        Locator {
+            path: path.to_string(),
+            options: options
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    fn token(token: &str) -> VaultSettings {
+        VaultSettings {
+            token: Some(token.to_string()),
+            ..VaultSettings::default()
+        }
+    }
+
+    fn kv2(data: serde_json::Value) -> serde_json::Value {
+        json!({"data": {"data": data, "metadata": {"version": 2}}})
+    }
+
+    #[tokio::test]
+    async fn reads_latest_and_selected_versions() {
+        let server = StandIn::start(vec![
+            (
+                "GET /v1/secret/data/app",
+                200,
+                kv2(json!({"password": "new"})),
+            ),
+            (
+                "GET /v1/secret/data/app?version=1",
+                200,
+                kv2(json!({"password": "old"})),
+            ),
+        ]);
+        let provider = KeyValue(server.vault(VaultSettings {
+            namespace: Some("team".to_string()),
+            ..token("s.token")
+        }));
+
+        assert_eq!(
+            provider
+                .resolve(&locator("secret/data/app", &[]))
+                .await
+                .unwrap(),
+            Secret::Text(r#"{"password":"new"}"#.to_string())
+        );
+        assert_eq!(
+            provider
+                .resolve(&locator("secret/data/app", &[("version", "1")]))
+                .await
+                .unwrap(),
+            Secret::Text(r#"{"password":"old"}"#.to_string())
+        );
+
+        let received = server.received();
+        assert_eq!(received.len(), 2);
+        for request in received {
+            assert_eq!(request.token.as_deref(), Some("s.token"));
+            assert_eq!(request.namespace.as_deref(), Some("team"));
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_missing_deleted_and_malformed_secrets() {
+        let server = StandIn::start(vec![
+            (
+                "GET /v1/secret/data/app?version=1",
+                200,
+                kv2(serde_json::Value::Null),
+            ),
+            ("GET /v1/kv/app", 200, json!({"data": {"password": "v1"}})),
+        ]);
+        let provider = KeyValue(server.vault(token("s.token")));
+
+        let provider = &provider;
+        let resolve = |path, options| {
+            let locator = locator(path, options);
+            async move { provider.resolve(&locator).await }
+        };
+
+        assert!(matches!(
+            resolve("secret/data/app", &[("version", "1")]).await,
+            Err(ProviderError::NotFound(message)) if message == "secret/data/app has been deleted"
+        ));
+        assert!(matches!(
+            resolve("secret/data/missing", &[]).await,
+            Err(ProviderError::NotFound(_))
+        ));
+        assert!(matches!(
+            resolve("kv/app", &[]).await,
+            Err(ProviderError::Invalid(_))
+        ));
+        assert!(matches!(
+            resolve("secret/data/app", &[("version", "latest")]).await,
+            Err(ProviderError::Invalid(_))
+        ));
+
+        // The invalid version is rejected without a request
+        assert_eq!(server.received().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_approle() {
+        let server = StandIn::start(vec![
+            (
+                "POST /v1/auth/approle/login",
+                200,
+                json!({"auth": {"client_token": "s.approle", "lease_duration": 60, "renewable": true}}),
+            ),
+            (
+                "GET /v1/secret/data/app",
+                200,
+                kv2(json!({"password": "hunter2"})),
+            ),
+        ]);
+        let vault = server.vault(VaultSettings {
+            auth: VaultAuth::Approle,
+            role_id: Some("role".to_string()),
+            secret_id: Some("secret".to_string()),
+            ..VaultSettings::default()
+        });
+
+        KeyValue(vault.clone())
+            .resolve(&locator("secret/data/app", &[]))
+            .await
+            .unwrap();
+
+        let received = server.received();
+        assert_eq!(received[0].request, "POST /v1/auth/approle/login");
+        assert_eq!(
+            received[0].body,
+            json!({"role_id": "role", "secret_id": "secret"})
+        );
+        assert_eq!(received[1].token.as_deref(), Some("s.approle"));
+
+        let lease = vault.token_lease().await.unwrap();
+        assert_eq!(lease.duration, Duration::from_secs(60));
+        assert!(lease.renewable);
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_kubernetes() {
+        let server = StandIn::start(vec![
+            (
+                "POST /v1/auth/k8s/login",
+                200,
+                json!({"auth": {"client_token": "s.kubernetes"}}),
+            ),
+            (
+                "GET /v1/secret/data/app",
+                200,
+                kv2(json!({"password": "hunter2"})),
+            ),
+        ]);
+
+        let mut jwt = tempfile::NamedTempFile::new().unwrap();
+        writeln!(jwt, "service-account-jwt").unwrap();
+
+        KeyValue(server.vault(VaultSettings {
+            auth: VaultAuth::Kubernetes,
+            auth_mount: Some("k8s".to_string()),
+            role: Some("app".to_string()),
+            kubernetes_token_path: jwt.path().to_path_buf(),
+            ..VaultSettings::default()
+        }))
+        .resolve(&locator("secret/data/app", &[]))
+        .await
+        .unwrap();
+
+        let received = server.received();
+        assert_eq!(received[0].request, "POST /v1/auth/k8s/login");
+        assert_eq!(
+            received[0].body,
+            json!({"role": "app", "jwt": "service-account-jwt"})
+        );
+        assert_eq!(received[1].token.as_deref(), Some("s.kubernetes"));
+    }
+
+    #[tokio::test]
+    async fn rejected_login_is_access_denied() {
+        let server = StandIn::start(vec![(
+            "POST /v1/auth/approle/login",
+            400,
+            json!({"errors": ["invalid role or secret ID"]}),
+        )]);
+        let provider = KeyValue(server.vault(VaultSettings {
+            auth: VaultAuth::Approle,
+            role_id: Some("role".to_string()),
+            ..VaultSettings::default()
+        }));
+
+        assert!(matches!(
+            provider.resolve(&locator("secret/data/app", &[])).await,
+            Err(ProviderError::AccessDenied(message))
+                if message == "login to auth/approle failed: HTTP 400: invalid role or secret ID"
+        ));
+    }
+
+    #[test]
+    fn classifies_error_responses() {
+        let error = |status, body| status_error(StatusCode::from_u16(status).unwrap(), body);
+
+        assert!(matches!(
+            error(404, r#"{"errors": []}"#),
+            ProviderError::NotFound(message) if message == "HTTP 404: no message"
+        ));
+        assert!(matches!(
+            error(403, r#"{"errors": ["permission denied", "bad token"]}"#),
+            ProviderError::AccessDenied(message) if message == "HTTP 403: permission denied; bad token"
+        ));
+        assert!(matches!(error(401, ""), ProviderError::AccessDenied(_)));
+        assert!(matches!(error(400, "{}"), ProviderError::Invalid(_)));
+        assert!(matches!(error(429, "{}"), ProviderError::Throttled(_)));
+        assert!(matches!(
+            error(503, r#"{"errors": ["Vault is sealed"]}"#),
+            ProviderError::Transport(_)
+        ));
+        assert!(matches!(error(412, "not json"), ProviderError::Service(_)));
+    }
+
+    #[tokio::test]
+    async fn records_renews_and_revokes_dynamic_secret_leases() {
+        let lease =
+            json!({"lease_id": "database/creds/app/abc", "lease_duration": 60, "renewable": true});
+        let mut credentials = lease.clone();
+        credentials["data"] = json!({"username": "v-app", "password": "pw"});
+
+        let server = StandIn::start(vec![
+            ("GET /v1/database/creds/app", 200, credentials),
+            (
+                "GET /v1/auth/token/lookup-self",
+                200,
+                json!({"data": {"ttl": 120, "renewable": true}}),
+            ),
+            ("POST /v1/sys/leases/renew", 200, lease),
+            (
+                "POST /v1/auth/token/renew-self",
+                200,
+                json!({"auth": {"client_token": "s.token", "lease_duration": 90, "renewable": true}}),
+            ),
+            ("POST /v1/sys/leases/revoke", 204, serde_json::Value::Null),
+        ]);
+        let provider = DynamicSecret(server.vault(token("s.token")));
+        assert!(provider.leases().is_empty());
+
+        assert_eq!(
+            provider
+                .resolve(&locator("database/creds/app", &[]))
+                .await
+                .unwrap(),
+            Secret::Text(r#"{"password":"pw","username":"v-app"}"#.to_string())
+        );
+
+        let leases = provider.leases();
+        let credentials = Lease {
+            id: "database/creds/app/abc".to_string(),
+            duration: Duration::from_secs(60),
+            renewable: true,
+        };
+        let token = Lease {
+            id: TOKEN_LEASE_ID.to_string(),
+            duration: Duration::from_secs(120),
+            renewable: true,
+        };
+        assert_eq!(leases, [credentials.clone(), token.clone()]);
+
+        assert_eq!(
+            provider.renew_lease(&credentials).await.unwrap(),
+            credentials
+        );
+        assert_eq!(
+            provider.renew_lease(&token).await.unwrap().duration,
+            Duration::from_secs(90)
+        );
+        provider.revoke_lease(&credentials).await.unwrap();
+        // The token is left to expire, as it may be the user's own
+        provider.revoke_lease(&token).await.unwrap();
+
+        let received = server.received();
+        let requests = received
+            .iter()
+            .map(|received| received.request.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requests,
+            [
+                "GET /v1/database/creds/app",
+                "GET /v1/auth/token/lookup-self",
+                "POST /v1/sys/leases/renew",
+                "POST /v1/auth/token/renew-self",
+                "POST /v1/sys/leases/revoke",
+            ]
+        );
+        assert_eq!(
+            received[2].body,
+            json!({"lease_id": "database/creds/app/abc"})
+        );
+        assert_eq!(
+            received[4].body,
+            json!({"lease_id": "database/creds/app/abc"})
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::provider::{Provider, ProviderError};
+use crate::secret::BinaryMode;
+
+///
+/// Suffix on a scheme that turns a single-value reference into one that
+/// expands every top-level key of a JSON value into its own variable
+/// (`aws_sm_expand::prod/db`).
+///
+pub const EXPAND_SUFFIX: &str = "_expand";
+
+///
+/// What a provider is asked to fetch: a path and any `?key=value` options.
+///
+/// Two references with the same scheme and locator are fetched once.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Locator {
+    pub path: String,
+    pub options: BTreeMap<String, String>,
+}
+
+impl Locator {
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+}
+
+///
+/// A parsed `scheme::path[?key=value&...][#field]` reference.
+///
+/// The `?` options are only recognised for providers that accept options
+/// or return binary values, and the `#field` suffix only for providers
+/// whose values may be JSON documents and whose paths are not free-form
+/// text; for every other provider the whole remainder is the path.
+///
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub scheme: String,
+    pub locator: Locator,
+    pub field: Option<String>,
+    pub expand: bool,
+    pub binary: BinaryMode,
+
+    ///
+    /// Value used when the reference is not found, from the `default` option.
+    ///
+    pub default: Option<String>,
+}
+
+///
+/// Case conversion applied to JSON keys when expanding them into variable names.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Case {
+    Upper,
+    Lower,
+    Preserve,
+}
+
+impl Reference {
+    pub fn parse(
+        scheme: &str,
+        remainder: &str,
+        provider: &dyn Provider,
+        expand: bool,
+    ) -> Result<Self, ProviderError> {
+        // At the last `#`, so paths may contain `#`; a trailing `#` selects nothing
+        let (remainder, field) = match remainder.rsplit_once('#') {
+            Some((remainder, field)) if provider.field_suffix() => {
+                (remainder, (!field.is_empty()).then(|| field.to_string()))
+            }
+            _ => (remainder, None),
+        };
+
+        let mut accepted = provider.options().to_vec();
+        if provider.capabilities().binary {
+            accepted.push("binary");
+        }
+        // Only where `?` already starts options, so other paths are unaffected
+        if !accepted.is_empty() {
+            accepted.push("default");
+        }
+
+        let (path, mut options) = match remainder.split_once('?') {
+            Some((path, query)) if !accepted.is_empty() => (path, parse_options(query, &accepted)?),
+            _ => (remainder, BTreeMap::new()),
+        };
+
+        // Handled here rather than by the provider, and not part of what is fetched
+        let binary = match options.remove("binary") {
+            Some(mode) => BinaryMode::parse(&mode)?,
+            None => BinaryMode::default(),
+        };
+
+        let default = options.remove("default");
+        if default.is_some() && expand {
+            return Err(ProviderError::Invalid(
+                "default cannot be used when expanding a JSON value".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            scheme: scheme.to_string(),
+            locator: Locator {
+                path: path.to_string(),
+                options,
+            },
+            field,
+            expand,
+            binary,
+            default,
+        })
+    }
+
+    ///
+    /// Apply the `#field` selection, if any, to a resolved value.
+    ///
+    /// A field starting with `/` is treated as a JSON pointer
+    /// (`#/database/password`), anything else as a top-level key.
+    ///
+    pub fn select(&self, value: String) -> Result<String, ProviderError> {
+        if self.field.is_none() {
+            return Ok(value);
+        }
+
+        self.selected(&value)
+            .map(|selected| json_to_string(&selected))
+    }
+
+    ///
+    /// Split the selected JSON object into its top-level `(key, value)` pairs.
+    ///
+    pub fn expand(&self, value: String) -> Result<Vec<(String, String)>, ProviderError> {
+        match self.selected(&value)? {
+            serde_json::Value::Object(object) => Ok(object
+                .iter()
+                .map(|(key, value)| (key.clone(), json_to_string(value)))
+                .collect()),
+            _ => Err(ProviderError::Invalid(format!(
+                "{self} is not a JSON object, cannot expand it"
+            ))),
+        }
+    }
+
+    fn selected(&self, value: &str) -> Result<serde_json::Value, ProviderError> {
+        let document = serde_json::from_str::<serde_json::Value>(value).map_err(|error| {
+            ProviderError::Invalid(format!(
+                "{} is not a JSON document: {error}",
+                self.locator.path
+            ))
+        })?;
+
+        let Some(field) = &self.field else {
+            return Ok(document);
+        };
+
+        let selected = if field.starts_with('/') {
+            document.pointer(field)
+        } else {
+            document.get(field)
+        };
+
+        selected.cloned().ok_or_else(|| {
+            ProviderError::Invalid(format!(
+                "field {field} not present in {}",
+                self.locator.path
+            ))
+        })
+    }
+}
+
+///
+/// Parse `key=value&key=value` options, rejecting any the provider does not accept.
+///
+fn parse_options(
+    query: &str,
+    accepted: &[&str],
+) -> Result<BTreeMap<String, String>, ProviderError> {
+    let mut options = BTreeMap::new();
+
+    for option in query.split('&').filter(|option| !option.is_empty()) {
+        let Some((key, value)) = option.split_once('=') else {
+            return Err(ProviderError::Invalid(format!(
+                "option {option} has no value, expected {option}=<value>"
+            )));
+        };
+
+        if !accepted.contains(&key) {
+            return Err(ProviderError::Invalid(format!(
+                "unknown option {key}, expected one of: {}",
+                accepted.join(", ")
+            )));
+        }
+
+        if options.insert(key.to_string(), value.to_string()).is_some() {
+            return Err(ProviderError::Invalid(format!("option {key} given twice")));
+        }
+    }
+
+    Ok(options)
+}
+
+///
+/// The variable name a JSON key expands to: `<prefix><separator><KEY>`, with
+/// the key case-converted and anything that is not alphanumeric replaced by `_`.
+///
+pub fn expanded_name(prefix: &str, separator: &str, key: &str, case: Case) -> String {
+    let key = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    let key = match case {
+        Case::Upper => key.to_ascii_uppercase(),
+        Case::Lower => key.to_ascii_lowercase(),
+        Case::Preserve => key,
+    };
+
+    if prefix.is_empty() {
+        key
+    } else {
+        format!("{prefix}{separator}{key}")
+    }
+}
+
+///
+/// Replace every `${name}` in `text` with its value from `lookup`, and `$$`
+/// with a literal `$`.
+///
+pub fn interpolate(
+    text: &str,
+    mut lookup: impl FnMut(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let Some(end) = after.find('}') else {
+                return Err(format!("unterminated ${{ in {text}"));
+            };
+            let name = &after[..end];
+            match lookup(name) {
+                Some(value) => result.push_str(&value),
+                None => return Err(format!("${{{name}}} is not defined")),
+            }
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = if self.expand { EXPAND_SUFFIX } else { "" };
+        write!(f, "{}{suffix}::{}", self.scheme, self.locator.path)?;
+        for (index, (key, value)) in self.locator.options.iter().enumerate() {
+            let separator = if index == 0 { '?' } else { '&' };
+            write!(f, "{separator}{key}={value}")?;
+        }
+        if self.binary == BinaryMode::File {
+            let separator = if self.locator.options.is_empty() {
+                '?'
+            } else {
+                '&'
+            };
+            write!(f, "{separator}binary=file")?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, "#{field}")?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Render a JSON value as an environment variable value: strings are used
+/// verbatim, `null` becomes empty and everything else is serialized as JSON.
+///
+pub fn json_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(string) => string.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Capabilities;
+    use crate::secret::Secret;
+
+    struct Stub {
+        capabilities: Capabilities,
+        options: &'static [&'static str],
+        field_suffix: bool,
+    }
+
+    impl Stub {
+        fn json(options: &'static [&'static str]) -> Self {
+            Self {
+                capabilities: Capabilities {
+                    json_fields: true,
+                    ..Capabilities::default()
+                },
+                options,
+                field_suffix: true,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for Stub {
+        fn scheme(&self) -> &'static str {
+            "stub"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            self.capabilities
+        }
+
+        fn options(&self) -> &'static [&'static str] {
+            self.options
+        }
+
+        fn field_suffix(&self) -> bool {
+            self.field_suffix
+        }
+
+        async fn resolve(&self, _: &Locator) -> Result<Secret, ProviderError> {
+            unreachable!()
+        }
+    }
+
+    fn parse(remainder: &str, provider: &Stub) -> Result<Reference, ProviderError> {
+        Reference::parse("stub", remainder, provider, false)
+    }
+
+    #[test]
+    fn parses_options_and_field() {
+        let reference = parse("prod/db?version=3#password", &Stub::json(&["version"])).unwrap();
+
+        assert_eq!(reference.locator.path, "prod/db");
+        assert_eq!(reference.locator.option("version"), Some("3"));
+        assert_eq!(reference.field.as_deref(), Some("password"));
+        assert_eq!(reference.to_string(), "stub::prod/db?version=3#password");
+    }
+
+    #[test]
+    fn splits_field_at_last_hash() {
+        let reference = parse("/secrets/app#1/db.json#password", &Stub::json(&[])).unwrap();
+        assert_eq!(reference.locator.path, "/secrets/app#1/db.json");
+        assert_eq!(reference.field.as_deref(), Some("password"));
+
+        let reference = parse("/secrets/app#1/token#", &Stub::json(&[])).unwrap();
+        assert_eq!(reference.locator.path, "/secrets/app#1/token");
+        assert_eq!(reference.field, None);
+    }
+
+    #[test]
+    fn keeps_hash_and_question_mark_in_free_form_paths() {
+        let provider = Stub {
+            field_suffix: false,
+            ..Stub::json(&[])
+        };
+
+        let reference = parse("echo ${X#prefix} a?b", &provider).unwrap();
+        assert_eq!(reference.locator.path, "echo ${X#prefix} a?b");
+        assert_eq!(reference.field, None);
+        assert!(reference.locator.options.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_options() {
+        let provider = Stub::json(&["version"]);
+
+        for remainder in [
+            "db?stage=AWSCURRENT",
+            "db?version",
+            "db?version=1&version=2",
+        ] {
+            assert!(matches!(
+                parse(remainder, &provider),
+                Err(ProviderError::Invalid(_))
+            ));
+        }
+
+        assert!(matches!(
+            Reference::parse("stub", "db?default=x", &provider, true),
+            Err(ProviderError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn selects_fields() {
+        let document = r#"{"password": "hunter2", "database": {"port": 5432}}"#;
+        let select = |remainder| {
+            parse(remainder, &Stub::json(&[]))
+                .unwrap()
+                .select(document.to_string())
+        };
+
+        assert_eq!(select("db#password").unwrap(), "hunter2");
+        assert_eq!(select("db#/database/port").unwrap(), "5432");
+        assert_eq!(select("db#database").unwrap(), r#"{"port":5432}"#);
+        assert!(matches!(
+            select("db#missing"),
+            Err(ProviderError::Invalid(_))
+        ));
+        assert_eq!(select("db").unwrap(), document);
+    }
+
+    #[test]
+    fn interpolates_variables() {
+        let lookup = |name: &str| (name == "USER").then(|| "app".to_string());
+
+        assert_eq!(
+            interpolate("postgres://${USER}@db/$$HOME$", lookup).unwrap(),
+            "postgres://app@db/$HOME$"
+        );
+        assert_eq!(
+            interpolate("${USER}-${HOST}", lookup).unwrap_err(),
+            "${HOST} is not defined"
+        );
+        assert_eq!(
+            interpolate("a ${USER", lookup).unwrap_err(),
+            "unterminated ${ in a ${USER"
+        );
+    }
+}
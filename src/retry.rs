@@ -0,0 +1,470 @@
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::provider::{Provider, ProviderError};
+use crate::reference::Locator;
+use crate::secret::Secret;
+
+///
+/// How provider calls are retried and bounded in time.
+///
+/// Every call is limited by `call_timeout`. Calls that fail with a
+/// retryable error (throttling, transport) are attempted up to
+/// `max_attempts` times with exponential backoff, and no call or delay
+/// extends past `deadline`.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: NonZeroU32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+
+    ///
+    /// Fraction of each delay that is randomised, from `0.0` (fixed delays)
+    /// to `1.0` (anywhere between zero and the full delay).
+    ///
+    pub jitter: f64,
+
+    pub call_timeout: Duration,
+    pub deadline: Option<Instant>,
+}
+
+impl RetryPolicy {
+    ///
+    /// The delay before the attempt following `attempt` (counting from 1).
+    ///
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+
+        delay.mul_f64(1.0 - self.jitter * fastrand::f64())
+    }
+
+    ///
+    /// Time left before the deadline, or `None` if there is no deadline.
+    ///
+    fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    ///
+    /// Resolve `locators` through `provider` (individually or as one batch),
+    /// retrying the locators whose calls fail with a retryable error.
+    ///
+    /// Returns one result per locator, in order. Providers that are not
+    /// idempotent are called only once.
+    ///
+    pub async fn resolve(
+        &self,
+        provider: &dyn Provider,
+        locators: &[&Locator],
+    ) -> Vec<Result<Secret, ProviderError>> {
+        if !provider.capabilities().idempotent {
+            return self.attempt(provider, locators).await;
+        }
+
+        let mut results = vec![None; locators.len()];
+        let mut pending = (0..locators.len()).collect::<Vec<_>>();
+        let mut attempt = 1;
+
+        loop {
+            let batch = pending
+                .iter()
+                .map(|&index| locators[index])
+                .collect::<Vec<_>>();
+            let outcome = self.attempt(provider, &batch).await;
+
+            let last = attempt >= self.max_attempts.get();
+            let delay = self.backoff(attempt);
+            let out_of_time = self.remaining().is_some_and(|remaining| remaining <= delay);
+
+            let mut retry = Vec::new();
+            for (index, result) in pending.into_iter().zip(outcome) {
+                match result {
+                    Err(error) if error.is_retryable() && !last && !out_of_time => {
+                        tracing::warn!(
+                            "Attempt {}/{} for {}::{} failed: {}, retrying in {:?}",
+                            attempt,
+                            self.max_attempts,
+                            provider.scheme(),
+                            locators[index].path,
+                            error,
+                            delay
+                        );
+                        retry.push(index);
+                    }
+                    Err(error) if attempt > 1 => {
+                        results[index] = Some(Err(error.with_attempts(attempt)));
+                    }
+                    result => results[index] = Some(result),
+                }
+            }
+
+            if retry.is_empty() {
+                break;
+            }
+
+            tokio::time::sleep(delay).await;
+            pending = retry;
+            attempt += 1;
+        }
+
+        results.into_iter().flatten().collect()
+    }
+
+    ///
+    /// A single attempt, bounded by the call timeout and the deadline.
+    ///
+    async fn attempt(
+        &self,
+        provider: &dyn Provider,
+        locators: &[&Locator],
+    ) -> Vec<Result<Secret, ProviderError>> {
+        let (timeout, reason) = match self.remaining() {
+            Some(remaining) if remaining < self.call_timeout => (remaining, "deadline exceeded"),
+            _ => (self.call_timeout, "call timed out"),
+        };
+
+        match tokio::time::timeout(timeout, call(provider, locators)).await {
+            Ok(results) => results,
+            Err(_) if !provider.capabilities().idempotent => {
+                // The provider may have completed the call, so it is not repeated
+                for locator in locators {
+                    tracing::warn!(
+                        "{}::{} was abandoned, anything it issued will not be revoked",
+                        provider.scheme(),
+                        locator.path
+                    );
+                }
+
+                let error = ProviderError::Service(format!("{reason} after {timeout:?}"));
+                vec![Err(error); locators.len()]
+            }
+            Err(_) => {
+                let error = ProviderError::Transport(format!("{reason} after {timeout:?}"));
+                vec![Err(error); locators.len()]
+            }
+        }
+    }
+}
+
+///
+/// Resolve `locators` through `provider` in a single call.
+///
+async fn call(
+    provider: &dyn Provider,
+    locators: &[&Locator],
+) -> Vec<Result<Secret, ProviderError>> {
+    match locators {
+        [locator] => vec![provider.resolve(locator).await],
+        locators => provider.resolve_batch(locators).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, VecDeque};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::provider::Capabilities;
+
+    ///
+    /// Answers each path with its scripted results in order, then with the
+    /// last one, recording which paths every call was made for.
+    ///
+    struct Scripted {
+        results: Mutex<HashMap<&'static str, VecDeque<Result<Secret, ProviderError>>>>,
+        calls: Mutex<Vec<(Instant, Vec<String>)>>,
+        capabilities: Capabilities,
+        delay: Duration,
+    }
+
+    impl Scripted {
+        fn new(results: Vec<(&'static str, Vec<Result<Secret, ProviderError>>)>) -> Self {
+            Self {
+                results: Mutex::new(
+                    results
+                        .into_iter()
+                        .map(|(path, results)| (path, results.into()))
+                        .collect(),
+                ),
+                calls: Mutex::new(Vec::new()),
+                capabilities: Capabilities {
+                    batch: Some(20),
+                    idempotent: true,
+                    ..Capabilities::default()
+                },
+                delay: Duration::ZERO,
+            }
+        }
+
+        fn next(&self, path: &str) -> Result<Secret, ProviderError> {
+            let mut results = self.results.lock().unwrap();
+            let results = results.get_mut(path).unwrap();
+            if results.len() > 1 {
+                results.pop_front().unwrap()
+            } else {
+                results[0].clone()
+            }
+        }
+
+        fn times(&self) -> Vec<Instant> {
+            let calls = self.calls.lock().unwrap();
+            calls.iter().map(|(time, _)| *time).collect()
+        }
+
+        fn paths(&self) -> Vec<Vec<String>> {
+            let calls = self.calls.lock().unwrap();
+            calls.iter().map(|(_, paths)| paths.clone()).collect()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for Scripted {
+        fn scheme(&self) -> &'static str {
+            "stub"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            self.capabilities
+        }
+
+        async fn resolve(&self, locator: &Locator) -> Result<Secret, ProviderError> {
+            self.resolve_batch(&[locator]).await.remove(0)
+        }
+
+        async fn resolve_batch(&self, locators: &[&Locator]) -> Vec<Result<Secret, ProviderError>> {
+            let paths = locators
+                .iter()
+                .map(|locator| locator.path.clone())
+                .collect();
+            self.calls.lock().unwrap().push((Instant::now(), paths));
+            tokio::time::sleep(self.delay).await;
+
+            locators
+                .iter()
+                .map(|locator| self.next(&locator.path))
+                .collect()
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(3).unwrap(),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.0,
+            call_timeout: Duration::from_secs(5),
+            deadline: None,
+        }
+    }
+
+    fn locators(paths: &[&str]) -> Vec<Locator> {
+        paths
+            .iter()
+            .map(|path| Locator {
+                path: path.to_string(),
+                options: BTreeMap::new(),
+            })
+            .collect()
+    }
+
+    async fn resolve(
+        policy: RetryPolicy,
+        provider: &Scripted,
+        paths: &[&str],
+    ) -> Vec<Result<Secret, ProviderError>> {
+        let locators = locators(paths);
+        policy
+            .resolve(provider, &locators.iter().collect::<Vec<_>>())
+            .await
+    }
+
+    fn text(value: &str) -> Result<Secret, ProviderError> {
+        Ok(Secret::Text(value.to_string()))
+    }
+
+    fn throttled() -> Result<Secret, ProviderError> {
+        Err(ProviderError::Throttled("Rate exceeded".to_string()))
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            max_backoff: Duration::from_millis(300),
+            ..policy()
+        };
+
+        let delays = [1, 2, 3, 4, 100].map(|attempt| policy.backoff(attempt));
+        assert_eq!(delays, [100, 200, 300, 300, 300].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn keeps_jitter_within_range() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..policy()
+        };
+
+        for _ in 0..1000 {
+            let delay = policy.backoff(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_only_throttling_and_transport_errors() {
+        let provider = Scripted::new(vec![
+            ("throttled", vec![throttled(), text("a")]),
+            (
+                "transport",
+                vec![
+                    Err(ProviderError::Transport("reset".to_string())),
+                    text("b"),
+                ],
+            ),
+            (
+                "denied",
+                vec![Err(ProviderError::AccessDenied("no".to_string()))],
+            ),
+            (
+                "missing",
+                vec![Err(ProviderError::NotFound("gone".to_string()))],
+            ),
+        ]);
+
+        let results = resolve(
+            policy(),
+            &provider,
+            &["throttled", "transport", "denied", "missing"],
+        )
+        .await;
+
+        assert_eq!(results[0].as_ref().unwrap(), &Secret::Text("a".to_string()));
+        assert_eq!(results[1].as_ref().unwrap(), &Secret::Text("b".to_string()));
+        assert_eq!(
+            results[2].as_ref().unwrap_err().to_string(),
+            "access denied: no"
+        );
+        assert_eq!(
+            results[3].as_ref().unwrap_err().to_string(),
+            "not found: gone"
+        );
+        assert_eq!(
+            provider.paths(),
+            [
+                vec!["throttled", "transport", "denied", "missing"],
+                vec!["throttled", "transport"]
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_after_max_attempts() {
+        let provider = Scripted::new(vec![("db", vec![throttled()])]);
+        let start = Instant::now();
+
+        let results = resolve(policy(), &provider, &["db"]).await;
+
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "throttled: Rate exceeded (after 3 attempts)"
+        );
+        let times = provider.times().into_iter().map(|time| time - start);
+        assert_eq!(
+            times.collect::<Vec<_>>(),
+            [0, 100, 300].map(Duration::from_millis)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_retrying_at_the_deadline() {
+        let provider = Scripted::new(vec![("db", vec![throttled()])]);
+        let policy = RetryPolicy {
+            max_attempts: NonZeroU32::new(10).unwrap(),
+            deadline: Some(Instant::now() + Duration::from_millis(250)),
+            ..policy()
+        };
+
+        let results = resolve(policy, &provider, &["db"]).await;
+
+        // The third attempt would start after the deadline
+        assert_eq!(provider.times().len(), 2);
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "throttled: Rate exceeded (after 2 attempts)"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cuts_calls_short_at_the_deadline() {
+        let mut provider = Scripted::new(vec![("db", vec![text("late")])]);
+        provider.delay = Duration::from_secs(60);
+        let policy = RetryPolicy {
+            max_attempts: NonZeroU32::new(1).unwrap(),
+            deadline: Some(Instant::now() + Duration::from_secs(2)),
+            ..policy()
+        };
+
+        let results = resolve(policy, &provider, &["db"]).await;
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "transport error: deadline exceeded after 2s"
+        );
+
+        let policy = RetryPolicy {
+            max_attempts: NonZeroU32::new(1).unwrap(),
+            ..self::policy()
+        };
+        let results = resolve(policy, &provider, &["db"]).await;
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "transport error: call timed out after 5s"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_only_the_failed_locators_of_a_batch() {
+        let provider = Scripted::new(vec![
+            ("a", vec![text("a")]),
+            ("b", vec![throttled(), throttled(), text("b")]),
+            ("c", vec![throttled(), text("c")]),
+        ]);
+
+        let results = resolve(policy(), &provider, &["a", "b", "c"]).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(
+            provider.paths(),
+            [vec!["a", "b", "c"], vec!["b", "c"], vec!["b"]]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_providers_that_are_not_idempotent_once() {
+        let mut provider = Scripted::new(vec![("creds", vec![throttled()])]);
+        provider.capabilities.idempotent = false;
+
+        let results = resolve(policy(), &provider, &["creds"]).await;
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "throttled: Rate exceeded"
+        );
+        assert_eq!(provider.times().len(), 1);
+
+        // A call that times out may have issued credentials, so it is not retried either
+        provider.delay = Duration::from_secs(60);
+        let results = resolve(policy(), &provider, &["creds"]).await;
+        let error = results[0].as_ref().unwrap_err();
+        assert!(!error.is_retryable());
+        assert_eq!(error.to_string(), "service error: call timed out after 5s");
+        assert_eq!(provider.times().len(), 2);
+    }
+}
@@ -0,0 +1,274 @@
+use std::cell::OnceCell;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+
+use crate::provider::ProviderError;
+
+///
+/// A value returned by a provider.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Secret {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+///
+/// How a binary value is exposed to the command, chosen per reference with
+/// the `binary` option.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BinaryMode {
+    ///
+    /// The variable holds the value base64-encoded.
+    ///
+    #[default]
+    Base64,
+
+    ///
+    /// The value is written to a file and the variable holds its path.
+    ///
+    File,
+}
+
+impl BinaryMode {
+    pub fn parse(mode: &str) -> Result<Self, ProviderError> {
+        match mode {
+            "base64" => Ok(BinaryMode::Base64),
+            "file" => Ok(BinaryMode::File),
+            other => Err(ProviderError::Invalid(format!(
+                "unknown binary mode {other}, expected base64 or file"
+            ))),
+        }
+    }
+}
+
+impl Secret {
+    ///
+    /// The value as text, for field selection and expansion.
+    ///
+    pub fn into_text(self) -> Result<String, ProviderError> {
+        match self {
+            Secret::Text(text) => Ok(text),
+            Secret::Binary(bytes) => String::from_utf8(bytes).map_err(|_| {
+                ProviderError::Invalid("value is binary and not UTF-8 text".to_string())
+            }),
+        }
+    }
+
+    ///
+    /// The value of the variable `name`: text is used as is, binary values
+    /// are exposed according to `mode`, with files written under `directory`.
+    ///
+    pub fn into_value(
+        self,
+        mode: BinaryMode,
+        name: &str,
+        directory: &BinaryDirectory,
+    ) -> Result<String, ProviderError> {
+        match (self, mode) {
+            (Secret::Text(text), _) => Ok(text),
+            (Secret::Binary(bytes), BinaryMode::Base64) => {
+                Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
+            (Secret::Binary(bytes), BinaryMode::File) => write_file(&bytes, name, directory)
+                .map(|path| path.to_string_lossy().into_owned())
+                .map_err(|error| {
+                    ProviderError::Invalid(format!("cannot write binary value to a file: {error}"))
+                }),
+        }
+    }
+}
+
+///
+/// Directory that binary values loaded with `binary=file` are written to,
+/// created on first use so that runs without such values leave nothing
+/// behind.
+///
+pub struct BinaryDirectory {
+    ///
+    /// The `--binary-dir` option, or `None` for a new private directory in
+    /// the system temporary directory.
+    ///
+    configured: Option<PathBuf>,
+    path: OnceCell<PathBuf>,
+}
+
+impl BinaryDirectory {
+    pub fn new(configured: Option<PathBuf>) -> Self {
+        Self {
+            configured,
+            path: OnceCell::new(),
+        }
+    }
+
+    fn path(&self) -> std::io::Result<&Path> {
+        if let Some(path) = self.path.get() {
+            return Ok(path);
+        }
+
+        let path = match &self.configured {
+            Some(directory) => {
+                prepare_directory(directory)?;
+                directory.clone()
+            }
+            // Randomly named and created exclusively with mode 0700, so no
+            // other user can have created it first or write to it. It is
+            // kept, as the command reads from it after the loader has exec'd
+            None => tempfile::Builder::new()
+                .prefix("environment-loader-")
+                .permissions(std::fs::Permissions::from_mode(0o700))
+                .tempdir()?
+                .keep(),
+        };
+
+        Ok(self.path.get_or_init(|| path))
+    }
+}
+
+///
+/// Create `directory` if needed, and make sure no other user can write to it
+/// or swap the files written there.
+///
+fn prepare_directory(directory: &Path) -> std::io::Result<()> {
+    match std::fs::DirBuilder::new().mode(0o700).create(directory) {
+        Err(error) if error.kind() == ErrorKind::AlreadyExists => {}
+        result => result?,
+    }
+
+    let metadata = std::fs::metadata(directory)?;
+    if !metadata.is_dir() {
+        return Err(std::io::Error::other(format!(
+            "{} is not a directory",
+            directory.display()
+        )));
+    }
+    if metadata.uid() != nix::unistd::geteuid().as_raw() || metadata.mode() & 0o022 != 0 {
+        return Err(std::io::Error::other(format!(
+            "{} must be owned by the current user and writable only by it",
+            directory.display()
+        )));
+    }
+
+    Ok(())
+}
+
+///
+/// Write `bytes` to a new file `<directory>/<name>`, readable only by the
+/// current user.
+///
+fn write_file(bytes: &[u8], name: &str, directory: &BinaryDirectory) -> std::io::Result<PathBuf> {
+    // The name must not lead the file out of the directory
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{name} is not usable as a file name"),
+        ));
+    }
+
+    let path = directory.path()?.join(name);
+
+    // A file left by an earlier run in a configured directory is replaced;
+    // if it is a symlink, the link itself is removed
+    match std::fs::remove_file(&path) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        result => result?,
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .custom_flags(nix::libc::O_NOFOLLOW)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(bytes)?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::symlink_metadata(path).unwrap().mode() & 0o777
+    }
+
+    #[test]
+    fn writes_private_files() {
+        let parent = tempfile::tempdir().unwrap();
+        let configured = parent.path().join("binary");
+        let directory = BinaryDirectory::new(Some(configured.clone()));
+
+        let path = write_file(b"\x00\x01", "TLS_KEY", &directory).unwrap();
+
+        assert_eq!(path, configured.join("TLS_KEY"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"\x00\x01");
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&configured), 0o700);
+    }
+
+    #[test]
+    fn writes_to_a_private_temporary_directory_by_default() {
+        let directory = BinaryDirectory::new(None);
+
+        let path = write_file(b"key", "TLS_KEY", &directory).unwrap();
+
+        let parent = path.parent().unwrap();
+        assert_eq!(mode(parent), 0o700);
+        assert_eq!(mode(&path), 0o600);
+        std::fs::remove_dir_all(parent).unwrap();
+    }
+
+    #[test]
+    fn replaces_symlinks_without_following_them() {
+        let directory = tempfile::tempdir().unwrap();
+        let victim = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(victim.path(), "original").unwrap();
+        symlink(victim.path(), directory.path().join("TLS_KEY")).unwrap();
+
+        let path = write_file(
+            b"key",
+            "TLS_KEY",
+            &BinaryDirectory::new(Some(directory.path().to_path_buf())),
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read_to_string(victim.path()).unwrap(), "original");
+        assert!(std::fs::symlink_metadata(&path).unwrap().is_file());
+        assert_eq!(std::fs::read(&path).unwrap(), b"key");
+    }
+
+    #[test]
+    fn rejects_directories_others_can_write_to() {
+        for permissions in [0o777, 0o770, 0o702] {
+            let directory = tempfile::tempdir().unwrap();
+            std::fs::set_permissions(
+                directory.path(),
+                std::fs::Permissions::from_mode(permissions),
+            )
+            .unwrap();
+
+            let binary = BinaryDirectory::new(Some(directory.path().to_path_buf()));
+            assert!(write_file(b"key", "TLS_KEY", &binary).is_err());
+            assert!(!directory.path().join("TLS_KEY").exists());
+        }
+    }
+
+    #[test]
+    fn rejects_names_outside_the_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        let binary = BinaryDirectory::new(Some(directory.path().join("binary")));
+
+        for name in ["", "a/b", "..", "../TLS_KEY", "/etc/passwd"] {
+            let error = write_file(b"key", name, &binary).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+        assert!(!directory.path().join("binary").exists());
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::time::Duration;
+
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use tokio::process::Child;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::time::Instant;
+
+use crate::provider::{Lease, Provider, ProviderError, Registry};
+
+///
+/// Renewal failures are retried until the lease has less than this left.
+///
+const MIN_RENEWAL_RETRY: Duration = Duration::from_secs(1);
+
+///
+/// A lease kept alive while the command runs.
+///
+struct Renewal<'a> {
+    provider: &'a dyn Provider,
+    lease: Lease,
+    expires: Instant,
+
+    ///
+    /// When to renew next, or `None` once the lease cannot be extended.
+    ///
+    due: Option<Instant>,
+}
+
+impl<'a> Renewal<'a> {
+    fn new(provider: &'a dyn Provider, lease: Lease) -> Self {
+        let now = Instant::now();
+
+        if !lease.renewable {
+            tracing::info!(
+                "Lease {} is not renewable and expires in {:?}",
+                lease.id,
+                lease.duration
+            );
+        }
+
+        Self {
+            provider,
+            expires: now + lease.duration,
+            due: lease.renewable.then(|| now + lease.duration * 2 / 3),
+            lease,
+        }
+    }
+
+    async fn renew(&mut self, timeout: Duration) {
+        let result = bounded(timeout, self.provider.renew_lease(&self.lease)).await;
+        let now = Instant::now();
+
+        match result {
+            Ok(lease) => {
+                tracing::debug!("Renewed lease {} for {:?}", lease.id, lease.duration);
+
+                // A shorter duration means the lease has reached its maximum TTL
+                if !lease.renewable || lease.duration < self.lease.duration {
+                    tracing::warn!(
+                        "Lease {} cannot be extended further and expires in {:?}",
+                        lease.id,
+                        lease.duration
+                    );
+                    self.due = None;
+                } else {
+                    self.due = Some(now + lease.duration * 2 / 3);
+                }
+
+                self.expires = now + lease.duration;
+                self.lease = lease;
+            }
+            Err(error) => {
+                let remaining = self.expires.saturating_duration_since(now);
+
+                if remaining > MIN_RENEWAL_RETRY {
+                    tracing::warn!(
+                        "Failed to renew lease {}: {}, retrying in {:?}",
+                        self.lease.id,
+                        error,
+                        remaining / 3
+                    );
+                    self.due = Some(now + remaining / 3);
+                } else {
+                    tracing::error!(
+                        "Failed to renew lease {}: {}, it expires in {:?}",
+                        self.lease.id,
+                        error,
+                        remaining
+                    );
+                    self.due = None;
+                }
+            }
+        }
+    }
+}
+
+///
+/// Run the command as a child process with `env` as its entire environment,
+/// renewing every lease held by the registry's providers until it exits and
+/// revoking them afterwards.
+///
+/// `SIGTERM`, `SIGINT` and `SIGHUP` are forwarded to the child. Every renewal
+/// and revocation is limited by `timeout`, so a provider that stops answering
+/// delays signals by at most that long. Returns the exit code the loader
+/// should exit with: the child's, or `128 + signal` if it was killed by a
+/// signal.
+///
+pub async fn run(
+    registry: &Registry,
+    cmd: &[String],
+    env: &HashMap<String, String>,
+    timeout: Duration,
+) -> i32 {
+    let mut renewals = registry
+        .leases()
+        .into_iter()
+        .map(|(provider, lease)| Renewal::new(provider, lease))
+        .collect::<Vec<_>>();
+
+    let spawned = tokio::process::Command::new(&cmd[0])
+        .args(&cmd[1..])
+        .env_clear()
+        .envs(env)
+        .spawn();
+
+    let code = match spawned {
+        Ok(mut child) => supervise(&mut child, &mut renewals, timeout).await,
+        Err(error) => {
+            tracing::error!("Failed to start {}: {}", cmd[0], error);
+            1
+        }
+    };
+
+    revoke(&renewals, timeout).await;
+
+    code
+}
+
+async fn supervise(child: &mut Child, renewals: &mut [Renewal<'_>], timeout: Duration) -> i32 {
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    let mut interrupt = signal(SignalKind::interrupt()).unwrap();
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+
+    let status = loop {
+        let due = renewals.iter().filter_map(|renewal| renewal.due).min();
+
+        tokio::select! {
+            status = child.wait() => break status,
+            Some(()) = terminate.recv() => forward(child, Signal::SIGTERM),
+            Some(()) = interrupt.recv() => forward(child, Signal::SIGINT),
+            Some(()) = hangup.recv() => forward(child, Signal::SIGHUP),
+            () = tokio::time::sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                let now = Instant::now();
+                futures::future::join_all(
+                    renewals
+                        .iter_mut()
+                        .filter(|renewal| renewal.due.is_some_and(|due| due <= now))
+                        .map(|renewal| renewal.renew(timeout)),
+                )
+                .await;
+            }
+        }
+    };
+
+    match status {
+        Ok(status) => status
+            .code()
+            .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+        Err(error) => {
+            tracing::error!("Failed to wait for the command: {}", error);
+            1
+        }
+    }
+}
+
+fn forward(child: &Child, signal: Signal) {
+    if let Some(pid) = child.id() {
+        let _ = signal::kill(Pid::from_raw(pid as i32), signal);
+    }
+}
+
+async fn revoke(renewals: &[Renewal<'_>], timeout: Duration) {
+    let revoked = futures::future::join_all(
+        renewals
+            .iter()
+            .map(|renewal| bounded(timeout, renewal.provider.revoke_lease(&renewal.lease))),
+    )
+    .await;
+
+    for (renewal, result) in renewals.iter().zip(revoked) {
+        match result {
+            Ok(()) => tracing::debug!("Revoked lease {}", renewal.lease.id),
+            Err(error) => {
+                tracing::warn!("Failed to revoke lease {}: {}", renewal.lease.id, error)
+            }
+        }
+    }
+}
+
+///
+/// Run a lease call, failing it if it takes longer than `timeout`.
+///
+async fn bounded<T>(
+    timeout: Duration,
+    call: impl Future<Output = Result<T, ProviderError>>,
+) -> Result<T, ProviderError> {
+    tokio::time::timeout(timeout, call)
+        .await
+        .unwrap_or_else(|_| {
+            Err(ProviderError::Transport(format!(
+                "call timed out after {timeout:?}"
+            )))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::provider::Capabilities;
+    use crate::reference::Locator;
+    use crate::secret::Secret;
+
+    ///
+    /// Answers renewals in order from `renewals`, never answering a `None`.
+    ///
+    struct Stub {
+        renewals: Mutex<VecDeque<Option<Result<Duration, ProviderError>>>>,
+    }
+
+    impl Stub {
+        fn new(
+            renewals: impl IntoIterator<Item = Option<Result<Duration, ProviderError>>>,
+        ) -> Self {
+            Self {
+                renewals: Mutex::new(renewals.into_iter().collect()),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for Stub {
+        fn scheme(&self) -> &'static str {
+            "stub"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::default()
+        }
+
+        async fn resolve(&self, _: &Locator) -> Result<Secret, ProviderError> {
+            unreachable!()
+        }
+
+        async fn renew_lease(&self, lease: &Lease) -> Result<Lease, ProviderError> {
+            let renewal = self.renewals.lock().unwrap().pop_front().unwrap();
+            let Some(renewal) = renewal else {
+                return std::future::pending().await;
+            };
+
+            renewal.map(|duration| Lease {
+                duration,
+                ..lease.clone()
+            })
+        }
+    }
+
+    fn lease(seconds: u64, renewable: bool) -> Lease {
+        Lease {
+            id: "database/creds/app/1".to_string(),
+            duration: Duration::from_secs(seconds),
+            renewable,
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[tokio::test(start_paused = true)]
+    async fn renews_at_two_thirds_of_the_lease() {
+        let provider = Stub::new([
+            Some(Ok(Duration::from_secs(30))),
+            Some(Ok(Duration::from_secs(12))),
+        ]);
+        let start = Instant::now();
+        let mut renewal = Renewal::new(&provider, lease(30, true));
+        assert_eq!(renewal.due, Some(start + Duration::from_secs(20)));
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        renewal.renew(TIMEOUT).await;
+        assert_eq!(renewal.due, Some(start + Duration::from_secs(40)));
+        assert_eq!(renewal.expires, start + Duration::from_secs(50));
+
+        // A shorter lease has reached its maximum TTL
+        tokio::time::advance(Duration::from_secs(20)).await;
+        renewal.renew(TIMEOUT).await;
+        assert_eq!(renewal.due, None);
+        assert_eq!(renewal.expires, start + Duration::from_secs(52));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_renew_leases_that_are_not_renewable() {
+        let provider = Stub::new([]);
+        let start = Instant::now();
+        let renewal = Renewal::new(&provider, lease(30, false));
+
+        assert_eq!(renewal.due, None);
+        assert_eq!(renewal.expires, start + Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_failed_renewals_while_the_lease_lasts() {
+        let failure = || {
+            Some(Err(ProviderError::Transport(
+                "connection refused".to_string(),
+            )))
+        };
+        let provider = Stub::new([failure(), failure()]);
+        let start = Instant::now();
+        let mut renewal = Renewal::new(&provider, lease(9, true));
+
+        tokio::time::advance(Duration::from_secs(6)).await;
+        renewal.renew(TIMEOUT).await;
+        assert_eq!(renewal.due, Some(start + Duration::from_secs(7)));
+
+        tokio::time::advance(Duration::from_millis(2500)).await;
+        renewal.renew(TIMEOUT).await;
+        assert_eq!(renewal.due, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_renewals_that_do_not_answer() {
+        let provider = Stub::new([None, Some(Ok(Duration::from_secs(30)))]);
+        let start = Instant::now();
+        let mut renewal = Renewal::new(&provider, lease(30, true));
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        renewal.renew(TIMEOUT).await;
+        assert_eq!(Instant::now(), start + Duration::from_secs(21));
+        assert_eq!(renewal.due, Some(start + Duration::from_secs(24)));
+
+        tokio::time::advance(Duration::from_secs(3)).await;
+        renewal.renew(TIMEOUT).await;
+        assert_eq!(renewal.due, Some(start + Duration::from_secs(44)));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::provider::ProviderError;
+use crate::reference::interpolate;
+
+///
+/// Scheme of references whose value is built from other variables
+/// (`template::postgres://${DB_USER}:${DB_PASSWORD}@${DB_HOST}/app`).
+///
+pub const TEMPLATE_SCHEME: &str = "template";
+
+///
+/// The variables a template refers to.
+///
+fn dependencies(template: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+
+    // Malformed templates have no dependencies and fail when rendered
+    let _ = interpolate(template, |name| {
+        names.insert(name.to_string());
+        Some(String::new())
+    });
+
+    names
+}
+
+///
+/// Render `templates`, keyed by the variable they are exported as, with the
+/// values of `variables` and of each other.
+///
+/// Templates are rendered once every template they refer to has been, so
+/// they may be chained in any order. A template fails if it refers to a
+/// variable that is not defined (`NotFound`), to a template that failed
+/// (with that template's error), or to itself through a cycle.
+///
+pub fn render(
+    templates: &BTreeMap<String, String>,
+    variables: &HashMap<String, String>,
+) -> BTreeMap<String, Result<String, ProviderError>> {
+    let mut pending = templates
+        .iter()
+        .map(|(name, template)| (name, (template, dependencies(template))))
+        .collect::<BTreeMap<_, _>>();
+    let mut results = BTreeMap::<String, Result<String, ProviderError>>::new();
+
+    loop {
+        let ready = pending
+            .iter()
+            .filter(|(_, (_, dependencies))| {
+                dependencies
+                    .iter()
+                    .all(|dependency| !pending.contains_key(dependency))
+            })
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+
+        if ready.is_empty() {
+            break;
+        }
+
+        for name in ready {
+            let (template, _) = pending.remove(name).unwrap();
+
+            let mut failed = None;
+            let mut undefined = false;
+            let rendered = interpolate(template, |dependency| match results.get(dependency) {
+                Some(Ok(value)) => Some(value.clone()),
+                Some(Err(error)) => {
+                    failed.get_or_insert_with(|| (dependency.to_string(), error.is_not_found()));
+                    Some(String::new())
+                }
+                None => {
+                    let value = variables.get(dependency).cloned();
+                    undefined |= value.is_none();
+                    value
+                }
+            });
+
+            let result = match (failed, rendered) {
+                (Some((dependency, true)), _) => Err(ProviderError::NotFound(format!(
+                    "template {dependency} could not be rendered"
+                ))),
+                (Some((dependency, false)), _) => Err(ProviderError::Invalid(format!(
+                    "template {dependency} could not be rendered"
+                ))),
+                (None, Err(message)) if undefined => Err(ProviderError::NotFound(message)),
+                (None, rendered) => rendered.map_err(ProviderError::Invalid),
+            };
+            results.insert(name.clone(), result);
+        }
+    }
+
+    // Whatever is left is part of a cycle or depends on one
+    let reachable = pending
+        .keys()
+        .map(|name| {
+            let mut reached = BTreeSet::new();
+            let mut stack = vec![*name];
+            while let Some(next) = stack.pop() {
+                for dependency in &pending[next].1 {
+                    if let Some((dependency, _)) = pending.get_key_value(dependency)
+                        && reached.insert(*dependency)
+                    {
+                        stack.push(dependency);
+                    }
+                }
+            }
+            (*name, reached)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    for (name, reached) in &reachable {
+        let error = if reached.contains(name) {
+            let cycle = reached
+                .iter()
+                .filter(|other| reachable[**other].contains(name))
+                .map(|other| other.as_str())
+                .collect::<Vec<_>>();
+            format!("dependency cycle between templates {}", cycle.join(", "))
+        } else {
+            let dependency = pending[*name]
+                .1
+                .iter()
+                .find(|dependency| pending.contains_key(dependency))
+                .unwrap();
+            format!("template {dependency} could not be rendered")
+        };
+        results.insert((*name).clone(), Err(ProviderError::Invalid(error)));
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(templates: &[(&str, &str)]) -> BTreeMap<String, String> {
+        templates
+            .iter()
+            .map(|(name, template)| (name.to_string(), template.to_string()))
+            .collect()
+    }
+
+    fn message(result: &Result<String, ProviderError>) -> String {
+        match result {
+            Err(ProviderError::Invalid(message)) => message.clone(),
+            other => panic!("expected an invalid template, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn renders_chained_templates_in_any_order() {
+        let variables = HashMap::from([("USER".to_string(), "app".to_string())]);
+        let results = render(
+            &templates(&[("A_URL", "${B_HOST}/db"), ("B_HOST", "${USER}@host")]),
+            &variables,
+        );
+
+        assert_eq!(results["A_URL"].as_ref().unwrap(), "app@host/db");
+        assert_eq!(results["B_HOST"].as_ref().unwrap(), "app@host");
+    }
+
+    #[test]
+    fn reports_cycles_separately_from_their_dependents() {
+        let results = render(
+            &templates(&[
+                ("A", "${B}"),
+                ("B", "${A}"),
+                ("C", "${C}"),
+                ("D", "${A}"),
+                ("E", "${D}"),
+            ]),
+            &HashMap::new(),
+        );
+
+        assert_eq!(
+            message(&results["A"]),
+            "dependency cycle between templates A, B"
+        );
+        assert_eq!(
+            message(&results["B"]),
+            "dependency cycle between templates A, B"
+        );
+        assert_eq!(
+            message(&results["C"]),
+            "dependency cycle between templates C"
+        );
+        assert_eq!(message(&results["D"]), "template A could not be rendered");
+        assert_eq!(message(&results["E"]), "template D could not be rendered");
+    }
+
+    #[test]
+    fn missing_variables_propagate_as_not_found() {
+        let results = render(
+            &templates(&[("A", "${MISSING}"), ("B", "${A}")]),
+            &HashMap::new(),
+        );
+
+        assert!(
+            matches!(&results["A"], Err(ProviderError::NotFound(message)) if message == "${MISSING} is not defined")
+        );
+        assert!(
+            matches!(&results["B"], Err(ProviderError::NotFound(message)) if message == "template A could not be rendered")
+        );
+    }
+}